@@ -0,0 +1,49 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+use rustcodingtest::{process_line, AmountPolicy, ChargebackPolicy, Client, ProcessOptions, RejectedTransaction, Strictness};
+
+fn fuzzed_options(flags: u8) -> ProcessOptions {
+    //The first byte of the input picks the options, so paths behind them such as overdrafts, evicted
+    //transactions and locked resolves are fuzzed too. Partial disputes come from dispute rows with an amount
+    let mut options = ProcessOptions::new().admin_transactions(true);
+    match flags & 0b11 {
+        1 => options = options.retain_transactions(1),
+        2 => options = options.retain_transactions(2),
+        _ => {}
+    }
+    match (flags >> 2) & 0b11 {
+        1 => options = options.overdraft_limit(Decimal::new(100, 0)),
+        2 => options = options.overdraft_limit(Decimal::MAX),
+        _ => {}
+    }
+    if flags & 0b1_0000 != 0 {
+        options = options.resolve_locked(true);
+    }
+    if flags & 0b10_0000 != 0 {
+        options = options.chargeback_policy(ChargebackPolicy::Never);
+    }
+    if flags & 0b100_0000 != 0 {
+        options = options.amount_policy(AmountPolicy::Error);
+    }
+    if flags & 0b1000_0000 != 0 {
+        options = options.strictness(Strictness::Strict);
+    }
+    options
+}
+
+//Feeds each line of the rest of the input through the row processing, keeping the accounts between
+//lines so later rows can dispute earlier ones. Errors are expected for bad input, only a panic is a failure
+fuzz_target!(|data: &[u8]| {
+    if let Some((&flags, Ok(text))) = data.split_first().map(|(flags, data)| (flags, std::str::from_utf8(data))) {
+        let mut clients: HashMap<u16, Client> = HashMap::new();
+        let mut rejected: Vec<RejectedTransaction> = Vec::new();
+        let options = fuzzed_options(flags);
+        for (index, line) in text.lines().enumerate() {
+            let _ = process_line(line, index as u64 + 1, &mut clients, &mut rejected, &options);
+        }
+    }
+});
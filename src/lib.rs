@@ -1,157 +1,3194 @@
-pub mod lib {
-    use std::error::Error;
-    use serde::Deserialize;
-
-    //Describes the valid structure of a row of data in the CSV
-    #[derive(Deserialize)]
-    pub struct Row<'a> {
-        pub transaction_type: &'a str,
-        pub client_id: u16,
-        pub transaction_id: u32,
-        pub amount: &'a str
-    }
- 
-    //Holds a valid deposit transaction. Only deposits can be disputed so all other 
-    //transactions are processed but not stored
-    #[derive(Debug)]
-    #[derive(Deserialize)]
-    pub struct ApprovedTransaction {
-        pub transaction_id: u32,
-        pub amount: f32,
-        pub in_dispute: bool
-    }
-
-    //Describes a client account with valid transactions.
-    #[derive(Debug)]
-    #[derive(Deserialize)]
-    pub struct Client {
-        pub client_id: u16,
-        pub current_transactions: Vec<ApprovedTransaction>, 
-        pub available: f32,
-        pub held: f32,
-        pub locked: bool
-    }
-
-    pub fn read_csv(filename: String, mut clients: &mut Vec<Client>) -> Result<(), Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(filename)?;
-        for result in rdr.records() {
-            let record = result?;
-            let row: Row = record.deserialize(None)?;
-            process_record(row, &mut clients);  
-        }   
-        Ok(())
-    }
-    
-    fn process_record(row: Row, clients: &mut Vec<Client>) {
-        //looks for a current client matching the client id in the row. 
-        //If found, then calls appropriate function the handle the transaction
-        //If no current client found, then creates a new client and processes the transaction
-        //New clients can only be created with an initial deposit transaction
-        let mut client_found = false;
-        let client_id = row.client_id;
-        let transaction_id = row.transaction_id;
-        let transaction_type = row.transaction_type; 
-        let mut amount = 0.0;
-        if row.amount != "" {
-            amount = row.amount.parse().unwrap();
-        }
-        for mut client in clients.iter_mut() {
-            if client.client_id == client_id {
-                client_found = true;
-                match transaction_type {
-                    "deposit"=> process_deposit(row, &mut client),
-                    "withdrawal"=> process_withdrawal(row, &mut client),
-                    "dispute"=>process_dispute(row, &mut client),
-                    "resolve"=>process_resolve(row, &mut client),
-                    "chargeback"=>process_chargeback(row, &mut client),
-                    _=>(),
-                };
-                break;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use flate2::read::GzDecoder;
+#[cfg(feature = "progress")]
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, warn};
+use rayon::prelude::*;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+//Describes the valid structure of a row of data in the CSV. Fields are matched to the header
+//names, and the amount, timestamp and currency columns may be left out entirely
+#[derive(Deserialize)]
+struct Row<'a> {
+    #[serde(rename = "type")]
+    transaction_type: &'a str,
+    #[serde(rename = "client")]
+    client_id: u16,
+    #[serde(rename = "tx")]
+    transaction_id: u32,
+    #[serde(default)]
+    amount: &'a str,
+    #[serde(default)]
+    timestamp: Option<&'a str>,
+    #[serde(default)]
+    currency: Option<&'a str>
+}
+
+//Every transaction type the engine can process, for giving them other names with
+//ProcessOptions::type_names. Freeze and Unfreeze are only processed when admin transactions are enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Hold,
+    Release,
+    Cancel,
+    Freeze,
+    Unfreeze
+}
+
+impl TransactionType {
+    //The name the type has in the type column by default
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Hold => "hold",
+            TransactionType::Release => "release",
+            TransactionType::Cancel => "cancel",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Unfreeze => "unfreeze",
+        }
+    }
+}
+
+//The kinds of transaction that are stored and can later be disputed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionKind::Deposit => write!(f, "deposit"),
+            TransactionKind::Withdrawal => write!(f, "withdrawal"),
+        }
+    }
+}
+
+//Whether a client account can still take transactions. Only an Active account can, a Frozen account
+//is held pending review and a ChargedBack account has been closed by a chargeback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    Frozen,
+    ChargedBack
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountStatus::Active => write!(f, "active"),
+            AccountStatus::Frozen => write!(f, "frozen"),
+            AccountStatus::ChargedBack => write!(f, "charged_back"),
+        }
+    }
+}
+
+//Holds a valid deposit or withdrawal transaction. Only deposits and withdrawals can be disputed
+//so all other transactions are processed but not stored
+//Part or all of the amount can be in dispute at once, disputed_amount is how much of it is held
+//A charged back transaction is final and can not be disputed again. Its disputed_amount is kept as
+//the amount that was charged back. State saved before partial disputes has no disputed_amount, as
+//only whole transactions could be disputed, so it loads as the full amount when disputed or charged back
+//resolved records that a dispute was resolved, so disputing it again can be forbidden. A pending
+//withdrawal has been placed on hold and its amount stays held until it is released or cancelled.
+//amount_raw is the amount as it was written in the input, less surrounding whitespace, before any
+//currency symbol or separators were removed, for auditing. State saved before these were added loads as never resolved,
+//not pending and with no raw amount
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(from = "SavedTransaction")]
+pub struct ApprovedTransaction {
+    pub transaction_id: u32,
+    pub kind: TransactionKind,
+    pub amount: Decimal,
+    pub in_dispute: bool,
+    pub disputed_amount: Decimal,
+    pub charged_back: bool,
+    #[serde(default)]
+    pub resolved: bool,
+    #[serde(default)]
+    pub pending: bool,
+    #[serde(default)]
+    pub amount_raw: String
+}
+
+//A stored transaction as it is saved, where disputed_amount may be missing from older state
+#[derive(Deserialize)]
+struct SavedTransaction {
+    transaction_id: u32,
+    kind: TransactionKind,
+    amount: Decimal,
+    in_dispute: bool,
+    #[serde(default)]
+    disputed_amount: Option<Decimal>,
+    charged_back: bool,
+    #[serde(default)]
+    resolved: bool,
+    #[serde(default)]
+    pending: bool,
+    #[serde(default)]
+    amount_raw: String
+}
+
+impl From<SavedTransaction> for ApprovedTransaction {
+    fn from(saved: SavedTransaction) -> Self {
+        let whole_amount = if saved.in_dispute || saved.charged_back { saved.amount } else { Decimal::ZERO };
+        ApprovedTransaction {
+            transaction_id: saved.transaction_id,
+            kind: saved.kind,
+            amount: saved.amount,
+            in_dispute: saved.in_dispute,
+            disputed_amount: saved.disputed_amount.unwrap_or(whole_amount),
+            charged_back: saved.charged_back,
+            resolved: saved.resolved,
+            pending: saved.pending,
+            amount_raw: saved.amount_raw
+        }
+    }
+}
+
+//Describes a client account with valid transactions.
+//Balances are fixed-point decimals so repeated transactions do not accumulate rounding errors
+//The ids of stored transactions are also kept in a set so duplicates can be found without a scan
+//Clients are serialized in full, so state saved with save_state can be loaded back with load_state
+//The total is kept up to date as the balances change rather than added up each time it is read. It is
+//not saved, and is worked out again from the balances whenever a client is deserialized
+//Transactions dropped to keep within a retention limit keep their ids in the set, and how much they added
+//to the total is kept so their balances can still be checked. State saved before this loads as none evicted
+//transactions_processed counts the transactions for the client in this run, for the per client limit,
+//and processed_ids holds the type and id of each of them when processing idempotently. Neither is saved
+//currency is the currency code of the account, taken from the first transaction accepted with one. All
+//of its balances are in that currency. Accounts of files without a currency column have none
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[serde(from = "SavedClient")]
+pub struct Client {
+    pub client_id: u16,
+    pub current_transactions: Vec<ApprovedTransaction>, 
+    pub transaction_ids: HashSet<u32>,
+    pub available: Decimal,
+    pub held: Decimal,
+    #[serde(skip)]
+    total: Decimal,
+    pub status: AccountStatus,
+    pub chargebacks: u32,
+    #[serde(default)]
+    pub evicted_total: Decimal,
+    #[serde(skip)]
+    pub transactions_processed: usize,
+    #[serde(skip)]
+    pub processed_ids: HashSet<(String, u32)>,
+    #[serde(default)]
+    pub currency: Option<String>
+}
+
+//A client as it is saved, which is every field of Client apart from the ones kept only for a run
+//State saved before account statuses has a locked flag instead, which could only be set by a chargeback
+#[derive(Deserialize)]
+struct SavedClient {
+    client_id: u16,
+    current_transactions: Vec<ApprovedTransaction>,
+    transaction_ids: HashSet<u32>,
+    available: Decimal,
+    held: Decimal,
+    #[serde(default)]
+    status: Option<AccountStatus>,
+    #[serde(default)]
+    locked: bool,
+    chargebacks: u32,
+    #[serde(default)]
+    evicted_total: Decimal,
+    #[serde(default)]
+    currency: Option<String>
+}
+
+impl From<SavedClient> for Client {
+    fn from(saved: SavedClient) -> Self {
+        Client {
+            client_id: saved.client_id,
+            current_transactions: saved.current_transactions,
+            transaction_ids: saved.transaction_ids,
+            available: saved.available,
+            held: saved.held,
+            total: saved.available + saved.held,
+            status: saved.status.unwrap_or(if saved.locked { AccountStatus::ChargedBack } else { AccountStatus::Active }),
+            chargebacks: saved.chargebacks,
+            evicted_total: saved.evicted_total,
+            transactions_processed: 0,
+            processed_ids: HashSet::new(),
+            currency: saved.currency
+        }
+    }
+}
+
+impl Client {
+    //A new active account with no funds or transactions, ready for apply_transaction
+    pub fn new(client_id: u16) -> Self {
+        Client {
+            client_id,
+            current_transactions: Vec::new(),
+            transaction_ids: HashSet::new(),
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            total: Decimal::ZERO,
+            status: AccountStatus::Active,
+            chargebacks: 0,
+            evicted_total: Decimal::ZERO,
+            transactions_processed: 0,
+            processed_ids: HashSet::new(),
+            currency: None
+        }
+    }
+
+    //An account that is not active is locked, and deposits and withdrawals can not be made on it
+    pub fn is_locked(&self) -> bool {
+        self.status != AccountStatus::Active
+    }
+
+    //Total funds in the account, both available and held
+    pub fn total(&self) -> Decimal {
+        debug_assert_eq!(self.total, self.available + self.held, "client {} has a total that does not match its balances", self.client_id);
+        self.total
+    }
+
+    //An account is overdrawn when a disputed deposit has already been withdrawn. The client then
+    //owes the disputed amount, so available goes negative while the amount is held
+    pub fn is_overdrawn(&self) -> bool {
+        self.available < Decimal::ZERO
+    }
+
+    //The total and held funds found by replaying the stored transactions and their dispute state, starting
+    //from the total of any evicted transactions. For every transaction charged back the disputed amount
+    //is the amount that was charged back
+    fn replayed_balances(&self) -> (Decimal, Decimal) {
+        let mut total = self.evicted_total;
+        let mut held = Decimal::ZERO;
+        for trans in &self.current_transactions {
+            let charged_back = if trans.charged_back { trans.disputed_amount } else { Decimal::ZERO };
+            match trans.kind {
+                TransactionKind::Deposit => total += trans.amount - charged_back,
+                TransactionKind::Withdrawal => total -= trans.amount - charged_back,
+            }
+            if trans.in_dispute {
+                held += trans.disputed_amount;
+                if trans.kind == TransactionKind::Withdrawal {
+                    total += trans.disputed_amount;
+                }
+            }
+            //A withdrawal on hold has not left the account yet
+            if trans.pending {
+                total += trans.amount;
+                held += trans.amount;
             }
         }
-        if !client_found {
-            if transaction_type == "deposit"{
-                let approved_trans = ApprovedTransaction{
-                    transaction_id: transaction_id,
-                    amount: amount,
-                    in_dispute: false
-                };
-                let mut new_client = Client {
-                    client_id: client_id,
-                    available: amount,
-                    held: 0.0,
-                    locked: false,
-                    current_transactions: Vec::new()
-                };
-                new_client.current_transactions.push(approved_trans);
-                clients.push(new_client);
-            }            
-        }
-    }
-    
-    fn process_deposit(row: Row, client: &mut Client) {
-        //Adds deposit to client account if the account is not locked due to a chargeback
-        //Deposit transactions and ammounts are stored for reference in case of a future dispute
-        if !client.locked {
-            let approved_trans = ApprovedTransaction{
-                transaction_id: row.transaction_id,
-                amount: row.amount.parse().unwrap(),
-                in_dispute: false
-            };
-            client.available += approved_trans.amount;
-            client.current_transactions.push(approved_trans);
-        }
-    }
-    
-    fn process_withdrawal(row: Row, client: &mut Client) {
-        //Withdraws amount from client account if the account is unlocked and has enough available funds
-        if !client.locked {
-            let amount = row.amount.parse().unwrap();
-            if client.available >= amount {
-                client.available -= amount;
-            }
-        }
-    }
-    
-    fn process_dispute(row: Row, client: &mut Client) {
-        //Marks a transaction as in dispute and moves the funds from available to held pending resolution
-        //If transaction is not found then dispute is discarded
-        if !client.locked {
-            for trans in client.current_transactions.iter_mut() {
-                if trans.transaction_id == row.transaction_id {
-                    trans.in_dispute = true;
-                    client.available -= trans.amount;
-                    client.held += trans.amount;   
+        (total, held)
+    }
+
+    fn debug_assert_balances(&self, overdraft_limit: Decimal) {
+        //Held funds can never be negative. Available funds can only go below the overdraft limit when
+        //disputed deposits have already been withdrawn, which requires an open dispute or a chargeback
+        debug_assert!(self.held >= Decimal::ZERO, "client {} has negative held funds", self.client_id);
+        debug_assert_eq!(self.total, self.available + self.held, "client {} has a total that does not match its balances", self.client_id);
+        debug_assert!(
+            self.available >= -overdraft_limit.max(Decimal::ZERO) || self.chargebacks > 0 || self.current_transactions.iter().any(|trans| trans.in_dispute),
+            "client {} has negative available funds outside of a dispute", self.client_id
+        );
+    }
+}
+
+//A one line summary of the account, leaving out the stored transactions shown by {:?}, as logged for
+//each transaction. write_output formats each column itself, as it follows the output precision, rounding
+//and chosen columns, and quotes fields as CSV
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "client {}: available {}, held {}, total {}, {}", self.client_id, self.available, self.held, self.total(), self.status)
+    }
+}
+
+//Describes the account details reported for a client once all transactions are processed
+#[derive(Debug)]
+#[derive(Serialize)]
+pub struct AccountSummary {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub status: AccountStatus,
+    pub overdrawn: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>
+}
+
+impl From<&Client> for AccountSummary {
+    fn from(client: &Client) -> Self {
+        AccountSummary {
+            client: client.client_id,
+            available: client.available,
+            held: client.held,
+            total: client.total(),
+            locked: client.is_locked(),
+            status: client.status,
+            overdrawn: client.is_overdrawn(),
+            currency: client.currency.clone()
+        }
+    }
+}
+
+impl AccountSummary {
+    //Rounds the reported amounts to the given number of decimal places
+    pub fn round_dp(self, decimal_places: u32, rounding: Rounding) -> Self {
+        let strategy = rounding.strategy();
+        AccountSummary {
+            available: self.available.round_dp_with_strategy(decimal_places, strategy),
+            held: self.held.round_dp_with_strategy(decimal_places, strategy),
+            total: self.total.round_dp_with_strategy(decimal_places, strategy),
+            ..self
+        }
+    }
+}
+
+//Formats that the client account details can be written in. Bincode is a compact binary format for
+//large outputs, read back with read_bincode_output. Only built with the bincode feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+    #[cfg(feature = "bincode")]
+    Bincode
+}
+
+//How amounts are rounded to the output precision. HalfEven, the default, rounds halves to the
+//nearest even digit, HalfUp rounds halves away from zero and Truncate drops the extra digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    HalfEven,
+    HalfUp,
+    Truncate
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+//The columns of the CSV account details, named as they are in its header. ClientId is the client
+//column under the name the library gives it, for consumers expecting that header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputColumn {
+    Client,
+    ClientId,
+    Available,
+    Held,
+    Total,
+    Locked,
+    Status,
+    Currency
+}
+
+impl OutputColumn {
+    //The columns written by default, in order
+    pub const DEFAULT: [OutputColumn; 6] = [OutputColumn::Client, OutputColumn::Available, OutputColumn::Held, OutputColumn::Total, OutputColumn::Locked, OutputColumn::Status];
+
+    //Every column that can be asked for, including the account currency which is only written when asked for
+    pub const ALL: [OutputColumn; 8] = [OutputColumn::Client, OutputColumn::ClientId, OutputColumn::Available, OutputColumn::Held, OutputColumn::Total, OutputColumn::Locked, OutputColumn::Status, OutputColumn::Currency];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputColumn::Client => "client",
+            OutputColumn::ClientId => "client_id",
+            OutputColumn::Available => "available",
+            OutputColumn::Held => "held",
+            OutputColumn::Total => "total",
+            OutputColumn::Locked => "locked",
+            OutputColumn::Status => "status",
+            OutputColumn::Currency => "currency",
+        }
+    }
+
+    fn value(self, client: &Client, options: &OutputOptions) -> String {
+        match self {
+            OutputColumn::Client | OutputColumn::ClientId => client.client_id.to_string(),
+            OutputColumn::Available => format_amount(client.available, options),
+            OutputColumn::Held => format_amount(client.held, options),
+            OutputColumn::Total => format_amount(client.total(), options),
+            OutputColumn::Locked => client.is_locked().to_string(),
+            OutputColumn::Status => client.status.to_string(),
+            OutputColumn::Currency => client.currency.clone().unwrap_or_default(),
+        }
+    }
+}
+
+//Controls how write_output formats the client account details
+//Summary adds the funds totalled across all clients and verbose adds the stored transactions of
+//every client after the account details. These and the choice of columns are for CSV output only
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub precision: usize,
+    pub rounding: Rounding,
+    pub summary: bool,
+    pub verbose: bool,
+    pub columns: Vec<OutputColumn>
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions { format: OutputFormat::Csv, precision: 4, rounding: Rounding::HalfEven, summary: false, verbose: false, columns: OutputColumn::DEFAULT.to_vec() }
+    }
+}
+
+//Totals the available funds, held funds and overall funds across all clients. For reconciliation
+//the overall total should equal the net of deposits, withdrawals and chargebacks
+//Totals too large to represent stop at the largest or smallest decimal rather than overflowing
+pub fn aggregate_totals(clients: &[Client]) -> (Decimal, Decimal, Decimal) {
+    let available = clients.iter().fold(Decimal::ZERO, |total, client| total.saturating_add(client.available));
+    let held = clients.iter().fold(Decimal::ZERO, |total, client| total.saturating_add(client.held));
+    (available, held, available.saturating_add(held))
+}
+
+fn format_amount(amount: Decimal, options: &OutputOptions) -> String {
+    //Rounds the amount to the requested number of decimal places, padding with zeros where needed
+    //The zeros are added here as formatting a very large decimal with a precision can panic
+    let precision = options.precision;
+    let text = amount.round_dp_with_strategy(precision as u32, options.rounding.strategy()).to_string();
+    let decimals = text.find('.').map_or(0, |point| text.len() - point - 1);
+    match (precision, decimals) {
+        (0, _) => text,
+        (_, 0) => format!("{}.{}", text, "0".repeat(precision)),
+        _ => format!("{}{}", text, "0".repeat(precision - decimals)),
+    }
+}
+
+//An account as written by the bincode output. The amounts are formatted to the output precision as
+//they are for CSV, as bincode can not read back the decimal type directly
+#[cfg(feature = "bincode")]
+#[derive(Serialize, Deserialize)]
+struct BinaryAccount {
+    client: u16,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    status: AccountStatus,
+    overdrawn: bool,
+    currency: Option<String>
+}
+
+#[cfg(feature = "bincode")]
+fn bincode_error(err: bincode::ErrorKind) -> io::Error {
+    //Failures to write or read are passed on as they are, anything else means the data is not valid
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+//Reads the account details written by write_output in the bincode format back into summaries
+#[cfg(feature = "bincode")]
+pub fn read_bincode_output<R: Read>(reader: R) -> io::Result<Vec<AccountSummary>> {
+    let records: Vec<BinaryAccount> = bincode::deserialize_from(reader).map_err(|err| bincode_error(*err))?;
+    let parse = |amount: &str| amount.parse::<Decimal>().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+    records.into_iter().map(|record| Ok(AccountSummary {
+        client: record.client,
+        available: parse(&record.available)?,
+        held: parse(&record.held)?,
+        total: parse(&record.total)?,
+        locked: record.locked,
+        status: record.status,
+        overdrawn: record.overdrawn,
+        currency: record.currency
+    })).collect()
+}
+
+//A row of the stored transactions listed by the verbose CSV output
+#[derive(Serialize)]
+struct TransactionRecord {
+    client: u16,
+    tx: u32,
+    #[serde(rename = "type")]
+    transaction_type: String,
+    amount: String,
+    in_dispute: bool
+}
+
+fn csv_writer<W: Write>(writer: W) -> csv::Writer<W> {
+    //Rows are written through a CSV writer so any field that needs it is quoted. The header lines
+    //are written separately, as they have a space after each comma
+    csv::WriterBuilder::new().has_headers(false).from_writer(writer)
+}
+
+//Writes the account details of each client to any writer, such as a buffered stdout, a file or a buffer
+//The writer is flushed once everything has been written
+pub fn write_output<W: Write>(clients: &[Client], mut writer: W, options: &OutputOptions) -> io::Result<()> {
+    match options.format {
+        OutputFormat::Csv => {
+            let header: Vec<&str> = options.columns.iter().map(|column| column.name()).collect();
+            writeln!(writer, "{}", header.join(", "))?;
+            let mut wtr = csv_writer(&mut writer);
+            for client in clients {
+                wtr.write_record(options.columns.iter().map(|column| column.value(client, options)))?;
+            }
+            wtr.flush()?;
+            drop(wtr);
+            if options.summary {
+                let (available, held, total) = aggregate_totals(clients);
+                writeln!(writer)?;
+                writeln!(writer, "available, held, total")?;
+                let mut wtr = csv_writer(&mut writer);
+                wtr.write_record([format_amount(available, options), format_amount(held, options), format_amount(total, options)])?;
+                wtr.flush()?;
+            }
+            if options.verbose {
+                writeln!(writer)?;
+                writeln!(writer, "client, tx, type, amount, in_dispute")?;
+                let mut wtr = csv_writer(&mut writer);
+                for client in clients {
+                    for trans in &client.current_transactions {
+                        wtr.serialize(TransactionRecord {
+                            client: client.client_id,
+                            tx: trans.transaction_id,
+                            transaction_type: trans.kind.to_string(),
+                            amount: format_amount(trans.amount, options),
+                            in_dispute: trans.in_dispute
+                        })?;
+                    }
                 }
+                wtr.flush()?;
             }
         }
+        OutputFormat::Json => {
+            let summaries: Vec<AccountSummary> = clients.iter().map(|client| AccountSummary::from(client).round_dp(options.precision as u32, options.rounding)).collect();
+            serde_json::to_writer_pretty(&mut writer, &summaries)?;
+            writeln!(writer)?;
+        }
+        #[cfg(feature = "bincode")]
+        OutputFormat::Bincode => {
+            let records: Vec<BinaryAccount> = clients.iter().map(|client| BinaryAccount {
+                client: client.client_id,
+                available: format_amount(client.available, options),
+                held: format_amount(client.held, options),
+                total: format_amount(client.total(), options),
+                locked: client.is_locked(),
+                status: client.status,
+                overdrawn: client.is_overdrawn(),
+                currency: client.currency.clone()
+            }).collect();
+            bincode::serialize_into(&mut writer, &records).map_err(|err| bincode_error(*err))?;
+        }
+    }
+    writer.flush()
+}
+
+//Every transaction currently in dispute, as the client id, transaction id and the amount held for it,
+//in client order
+pub fn disputed_transactions(clients: &[Client]) -> Vec<(u16, u32, Decimal)> {
+    clients.iter()
+        .flat_map(|client| client.current_transactions.iter().filter(|trans| trans.in_dispute).map(|trans| (client.client_id, trans.transaction_id, trans.disputed_amount)))
+        .collect()
+}
+
+//A transaction in dispute as written by write_disputes
+#[derive(Serialize)]
+struct DisputeRecord {
+    client: u16,
+    tx: u32,
+    amount: String
+}
+
+//Writes every transaction currently in dispute, in the same format and precision as write_output
+pub fn write_disputes<W: Write>(clients: &[Client], mut writer: W, options: &OutputOptions) -> io::Result<()> {
+    let records: Vec<DisputeRecord> = disputed_transactions(clients).into_iter()
+        .map(|(client, tx, amount)| DisputeRecord { client, tx, amount: format_amount(amount, options) })
+        .collect();
+    match options.format {
+        OutputFormat::Csv => {
+            writeln!(writer, "client, tx, amount")?;
+            let mut wtr = csv_writer(&mut writer);
+            for record in &records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &records)?;
+            writeln!(writer)?;
+        }
+        #[cfg(feature = "bincode")]
+        OutputFormat::Bincode => bincode::serialize_into(&mut writer, &records).map_err(|err| bincode_error(*err))?,
+    }
+    writer.flush()
+}
+
+//Saves the full state of every client, including stored transactions and open disputes, as JSON
+//so that processing can be resumed later with load_state
+pub fn save_state(clients: &[Client], path: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, clients)?;
+    writer.flush()
+}
+
+//Loads client state saved by save_state
+pub fn load_state(path: &str) -> io::Result<Vec<Client>> {
+    let clients = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(clients)
+}
+
+//Describes the ways processing a file can fail, so callers can tell a missing file
+//apart from malformed data
+#[derive(Debug)]
+pub enum ProcessError {
+    Io(io::Error),
+    Csv(csv::Error),
+    MissingAmount { transaction_id: u32 },
+    ParseAmount { transaction_id: u32, value: String },
+    InvalidAmount { transaction_id: u32, amount: Decimal },
+    UnknownTransactionType { line: u64, transaction_type: String },
+    IdOutOfRange { line: u64, field: &'static str, value: String, max: u64 },
+    Overflow { transaction_id: u32 },
+    NegativeHeld { transaction_id: u32, amount: Decimal, held: Decimal },
+    Json { line: u64, error: serde_json::Error },
+    Line { line: u64, error: Box<ProcessError> },
+    ThreadPool(rayon::ThreadPoolBuildError),
+    #[cfg(feature = "url")]
+    Http(reqwest::Error)
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessError::Io(err) => write!(f, "{}", err),
+            ProcessError::Csv(err) => write!(f, "{}", err),
+            ProcessError::MissingAmount { transaction_id } => write!(f, "missing amount for transaction {}", transaction_id),
+            ProcessError::ParseAmount { transaction_id, value } => write!(f, "invalid amount '{}' for transaction {}", value, transaction_id),
+            ProcessError::InvalidAmount { transaction_id, amount } => write!(f, "amount {} for transaction {} must be greater than zero", amount, transaction_id),
+            ProcessError::UnknownTransactionType { line, transaction_type } => write!(f, "unknown transaction type '{}' on line {}", transaction_type, line),
+            ProcessError::IdOutOfRange { line, field, value, max } => write!(f, "{} '{}' on line {} is out of range, it must be from 0 to {}", field, value, line, max),
+            ProcessError::Overflow { transaction_id } => write!(f, "transaction {} would make the account balance too large", transaction_id),
+            ProcessError::NegativeHeld { transaction_id, amount, held } => write!(f, "transaction {} would release {} from only {} held", transaction_id, amount, held),
+            ProcessError::Json { line, error } => write!(f, "invalid JSON on line {}: {}", line, error),
+            ProcessError::Line { line, error } => write!(f, "line {}: {}", line, error),
+            ProcessError::ThreadPool(err) => write!(f, "{}", err),
+            #[cfg(feature = "url")]
+            ProcessError::Http(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl ProcessError {
+    //Adds the line of the file the error was found on, unless the error already gives it
+    fn at_line(self, line: u64) -> Self {
+        match self {
+            ProcessError::UnknownTransactionType { .. } | ProcessError::IdOutOfRange { .. } | ProcessError::Json { .. } | ProcessError::Line { .. } => self,
+            error => ProcessError::Line { line, error: Box::new(error) },
+        }
+    }
+}
+
+impl Error for ProcessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProcessError::Io(err) => Some(err),
+            ProcessError::Csv(err) => Some(err),
+            ProcessError::Json { error, .. } => Some(error),
+            ProcessError::Line { error, .. } => Some(error.as_ref()),
+            ProcessError::ThreadPool(err) => Some(err),
+            #[cfg(feature = "url")]
+            ProcessError::Http(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProcessError {
+    fn from(err: io::Error) -> Self {
+        ProcessError::Io(err)
+    }
+}
+
+impl From<csv::Error> for ProcessError {
+    fn from(err: csv::Error) -> Self {
+        ProcessError::Csv(err)
+    }
+}
+
+//Controls what happens to a deposit or withdrawal whose amount is zero or negative.
+//Ignore skips the transaction, Error stops processing and reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountPolicy {
+    Ignore,
+    Error
+}
+
+//Controls what happens to a row with a transaction type that is not recognised.
+//Lenient adds the row to the rejected transactions, Strict stops processing and reports the line
+//It also controls a resolve or chargeback releasing more than is held, which Lenient warns about
+//and clamps held to zero for while Strict stops processing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    Lenient,
+    Strict
+}
+
+//Controls which transactions can open an account for a client not seen before. DepositOnly, the default,
+//rejects anything but a deposit for an unknown client. AnyTransaction opens an empty account for any
+//transaction, which is then applied to it, so a leading withdrawal is rejected for insufficient funds
+//but later deposits are still applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewClientPolicy {
+    DepositOnly,
+    AnyTransaction
+}
+
+//Controls when a chargeback locks the client account.
+//Always locks on the first chargeback, Never leaves the account open and AfterCount(n) locks on the nth chargeback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChargebackPolicy {
+    Always,
+    Never,
+    AfterCount(u32)
+}
+
+impl ChargebackPolicy {
+    fn locks_after(self, chargebacks: u32) -> bool {
+        match self {
+            ChargebackPolicy::Always => true,
+            ChargebackPolicy::Never => false,
+            ChargebackPolicy::AfterCount(count) => chargebacks >= count,
+        }
+    }
+}
+
+//A function called with the client id and transaction id whenever a chargeback locks an account. It is
+//shared behind a mutex as the options are, and so the function is, when processing on several threads
+#[derive(Clone)]
+struct LockCallback(Arc<Mutex<dyn FnMut(u16, u32) + Send>>);
+
+impl fmt::Debug for LockCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LockCallback")
+    }
+}
+
+impl LockCallback {
+    fn call(&self, client_id: u16, transaction_id: u32) {
+        //A callback that panicked on another thread is still called for later locks
+        let mut callback = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        callback(client_id, transaction_id);
+    }
+}
+
+//Options controlling how transactions are processed. The defaults reproduce the behaviour of process_file,
+//and each option is set through a builder method, e.g. ProcessOptions::new().strictness(Strictness::Strict)
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    amount_policy: AmountPolicy,
+    strictness: Strictness,
+    chargeback_policy: ChargebackPolicy,
+    resolve_locked: bool,
+    max_amount: Option<Decimal>,
+    threads: usize,
+    has_headers: bool,
+    delimiter: u8,
+    currency_symbol: Option<String>,
+    chronological: bool,
+    new_client_policy: NewClientPolicy,
+    admin_transactions: bool,
+    allow_redispute: bool,
+    type_names: HashMap<String, TransactionType>,
+    decimal_comma: bool,
+    overdraft_limit: Decimal,
+    disabled_types: Vec<TransactionType>,
+    retained_transactions: Option<usize>,
+    max_transactions_per_client: Option<usize>,
+    idempotent: bool,
+    on_lock: Option<LockCallback>
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            amount_policy: AmountPolicy::Ignore,
+            strictness: Strictness::Lenient,
+            chargeback_policy: ChargebackPolicy::Always,
+            resolve_locked: false,
+            max_amount: None,
+            threads: 1,
+            has_headers: true,
+            delimiter: b',',
+            currency_symbol: None,
+            chronological: false,
+            new_client_policy: NewClientPolicy::DepositOnly,
+            admin_transactions: false,
+            allow_redispute: true,
+            type_names: HashMap::new(),
+            decimal_comma: false,
+            overdraft_limit: Decimal::ZERO,
+            disabled_types: Vec::new(),
+            retained_transactions: None,
+            max_transactions_per_client: None,
+            idempotent: false,
+            on_lock: None
+        }
+    }
+}
+
+impl ProcessOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn amount_policy(mut self, amount_policy: AmountPolicy) -> Self {
+        self.amount_policy = amount_policy;
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    pub fn chargeback_policy(mut self, chargeback_policy: ChargebackPolicy) -> Self {
+        self.chargeback_policy = chargeback_policy;
+        self
+    }
+
+    pub fn new_client_policy(mut self, new_client_policy: NewClientPolicy) -> Self {
+        self.new_client_policy = new_client_policy;
+        self
+    }
+
+    //Other names for transaction types, such as credit for a deposit and debit for a withdrawal, used
+    //by files from other systems. The built in names are still accepted alongside them
+    pub fn type_names(mut self, type_names: HashMap<String, TransactionType>) -> Self {
+        self.type_names = type_names;
+        self
+    }
+
+    //Whether the freeze and unfreeze transaction types are accepted. They are treated as unknown
+    //types by default, as they should only be enabled for trusted input
+    pub fn admin_transactions(mut self, admin_transactions: bool) -> Self {
+        self.admin_transactions = admin_transactions;
+        self
+    }
+
+    //Transaction types that are skipped rather than processed, to see what the accounts would look like
+    //without them. Skipped rows are counted but are not rejected
+    pub fn disabled_types(mut self, disabled_types: Vec<TransactionType>) -> Self {
+        self.disabled_types = disabled_types;
+        self
+    }
+
+    //Whether a transaction can be disputed again after an earlier dispute of it was resolved, as it can
+    //by default. When forbidden such disputes are rejected
+    pub fn allow_redispute(mut self, allow_redispute: bool) -> Self {
+        self.allow_redispute = allow_redispute;
+        self
+    }
+
+    //Whether disputes still open on a locked account can be resolved. Deposits, withdrawals, new disputes
+    //and chargebacks on a locked account are discarded either way
+    pub fn resolve_locked(mut self, resolve_locked: bool) -> Self {
+        self.resolve_locked = resolve_locked;
+        self
+    }
+
+    //Rejects any deposit or withdrawal for more than this amount, to catch data entry errors.
+    //There is no limit by default
+    pub fn max_amount(mut self, max_amount: Decimal) -> Self {
+        self.max_amount = Some(max_amount);
+        self
+    }
+
+    //Stores at most this many deposits and withdrawals for each client, evicting the oldest when another is
+    //stored. Only the ids of evicted transactions are kept, so they can not be reused, and the transactions
+    //can no longer be disputed. Transactions in dispute or on hold are never evicted. Every transaction is
+    //kept by default. Panics if the limit is zero, as the transaction just stored is always kept
+    pub fn retain_transactions(mut self, retained_transactions: usize) -> Self {
+        assert!(retained_transactions > 0, "at least one transaction must be retained for each client");
+        self.retained_transactions = Some(retained_transactions);
+        self
+    }
+
+    //Calls the function with the client id and the id of the chargeback's transaction each time a
+    //chargeback locks an account, for alerting. Nothing is called by default
+    pub fn on_lock<F: FnMut(u16, u32) + Send + 'static>(mut self, callback: F) -> Self {
+        self.on_lock = Some(LockCallback(Arc::new(Mutex::new(callback))));
+        self
+    }
+
+    //Skips any transaction whose type and id have already been processed for the client in this run, so
+    //feeding the same file twice applies it once. Disputes, resolves and chargebacks name the transaction
+    //they refer to, so they are only skipped when of the same type, and a transaction can then only be
+    //disputed once
+    pub fn idempotent(mut self, idempotent: bool) -> Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    //Rejects every transaction for a client after it has made this many in the run, as an anti-fraud
+    //rule would. Rejected transactions count towards the limit too. There is no limit by default
+    pub fn max_transactions_per_client(mut self, max_transactions_per_client: usize) -> Self {
+        self.max_transactions_per_client = Some(max_transactions_per_client);
+        self
+    }
+
+    //How far below zero a withdrawal may take the available funds. Zero, the default, allows no overdraft,
+    //and a negative limit is treated as zero
+    pub fn overdraft_limit(mut self, overdraft_limit: Decimal) -> Self {
+        self.overdraft_limit = overdraft_limit.max(Decimal::ZERO);
+        self
+    }
+
+    //Processes the transactions of different clients in parallel on this many threads.
+    //One thread, the default, processes the file serially
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    //Whether the CSV starts with a header row. Files without one must have their columns in the
+    //order type, client, tx, amount
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    //The character separating the fields of each row, a comma by default. Tab, pipe and semicolon
+    //separated files can be read by setting it to b'\t', b'|' or b';'
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    //Reads amounts with a comma as the decimal point and periods between the thousands, as in 1.000,50,
+    //as files from many European systems are written. Used with a delimiter other than a comma
+    pub fn decimal_comma(mut self, decimal_comma: bool) -> Self {
+        self.decimal_comma = decimal_comma;
+        self
+    }
+
+    //A currency symbol, such as "$", that amounts may start with and that is removed before they are
+    //parsed. By default no symbol is removed, so an amount like $100.00 is invalid
+    pub fn currency_symbol(mut self, currency_symbol: &str) -> Self {
+        self.currency_symbol = Some(currency_symbol.to_string());
+        self
+    }
+
+    //Applies the transactions in the order of their timestamp column rather than file order. The whole
+    //file is read into memory and sorted before any transaction is processed, on a single thread
+    pub fn chronological(mut self, chronological: bool) -> Self {
+        self.chronological = chronological;
+        self
+    }
+}
+
+//Transaction types the engine knows how to process
+const TRANSACTION_TYPES: [&str; 8] = ["deposit", "withdrawal", "dispute", "resolve", "chargeback", "hold", "release", "cancel"];
+
+//Administrative transaction types, only known when enabled in the options as they should not be
+//accepted from untrusted input
+const ADMIN_TRANSACTION_TYPES: [&str; 2] = ["freeze", "unfreeze"];
+
+fn is_known_type(transaction_type: &str, options: &ProcessOptions) -> bool {
+    TRANSACTION_TYPES.contains(&transaction_type) || (options.admin_transactions && ADMIN_TRANSACTION_TYPES.contains(&transaction_type))
+}
+
+fn is_disabled_type(transaction_type: &str, options: &ProcessOptions) -> bool {
+    options.disabled_types.iter().any(|disabled| disabled.name() == transaction_type)
+}
+
+//Why a transaction was not applied to any client account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    //The transaction is for a client that has never made a deposit
+    UnknownClient,
+    //The transaction type is not one the engine knows how to process
+    UnknownTransactionType,
+    //A dispute, resolve or chargeback refers to a transaction the client does not have
+    TransactionNotFound,
+    //A dispute, resolve or chargeback refers to a transaction that was dropped to keep within the
+    //retention limit of the options
+    TransactionEvicted,
+    //A resolve or chargeback refers to a transaction that is not in dispute
+    NotDisputed,
+    //A release or cancel refers to a transaction that is not a withdrawal on hold
+    NotPending,
+    //A dispute refers to a transaction that has already been disputed and resolved, when the options
+    //do not allow disputing it again
+    AlreadyResolved,
+    //A dispute, resolve or chargeback refers to a transaction made by a different client
+    OtherClientTransaction { owner: u16 },
+    //A partial dispute is for more of the transaction than is not already in dispute
+    DisputeExceedsAmount { requested: Decimal, undisputed: Decimal },
+    //A deposit or withdrawal is for more than the largest amount allowed by the options
+    ExceedsMaxAmount { max_amount: Decimal },
+    //A deposit or withdrawal is for an account that is no longer active
+    AccountLocked { status: AccountStatus },
+    //A withdrawal asks for more than the client had available at the time
+    InsufficientFunds { requested: Decimal, available: Decimal },
+    //The client has already made as many transactions as the options allow in one run
+    TransactionLimit { limit: usize },
+    //The transaction is in a different currency from the client's account
+    CurrencyMismatch { currency: String, account_currency: String }
+}
+
+//Holds a copy of a transaction that was not applied so that it can be logged or audited,
+//along with the line of the file it was read from
+#[derive(Debug)]
+pub struct RejectedTransaction {
+    pub line: u64,
+    pub transaction_type: String,
+    pub client_id: u16,
+    pub transaction_id: u32,
+    pub amount: String,
+    pub reason: RejectReason
+}
+
+impl RejectedTransaction {
+    fn new(row: &Row, line: u64, reason: RejectReason) -> Self {
+        RejectedTransaction {
+            line,
+            transaction_type: row.transaction_type.to_string(),
+            client_id: row.client_id,
+            transaction_id: row.transaction_id,
+            amount: row.amount.to_string(),
+            reason
+        }
+    }
+}
+
+fn open_file(filename: &str) -> io::Result<Box<dyn Read>> {
+    //Opens a transaction file, decompressing it on the fly when it has a .gz extension
+    Ok(decompress(filename, File::open(filename)?))
+}
+
+fn decompress<R: Read + 'static>(filename: &str, reader: R) -> Box<dyn Read> {
+    if Path::new(filename).extension().is_some_and(|extension| extension == "gz") {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    }
+}
+
+//Counts the rows read of each transaction type. Rows of any other type, known or not, are only counted in rows
+//Rows of a type disabled in the options are also counted in skipped
+#[derive(Debug, Default, Clone)]
+pub struct TransactionCounts {
+    pub rows: usize,
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub disputes: usize,
+    pub resolves: usize,
+    pub chargebacks: usize,
+    pub skipped: usize
+}
+
+impl TransactionCounts {
+    fn add(&mut self, transaction_type: &str, options: &ProcessOptions) {
+        self.rows += 1;
+        if is_disabled_type(transaction_type, options) {
+            self.skipped += 1;
+        }
+        match transaction_type {
+            "deposit" => self.deposits += 1,
+            "withdrawal" => self.withdrawals += 1,
+            "dispute" => self.disputes += 1,
+            "resolve" => self.resolves += 1,
+            "chargeback" => self.chargebacks += 1,
+            _ => {}
+        }
     }
-    
-    fn process_resolve(row: Row, client: &mut Client) {
-        //If transaction is in dispute, marks ar not in dispute and moves ammount from held to available
-        //If transaction is not found then resolve is discarded
-        if !client.locked {
-            for trans in client.current_transactions.iter_mut() {
-                if trans.transaction_id == row.transaction_id && trans.in_dispute {
-                    trans.in_dispute = false;
-                    client.available += trans.amount;
-                    client.held -= trans.amount;
+}
+
+//Client accounts are keyed on client_id so each transaction can find its client directly
+//Transactions that could not be applied to any account are added to rejected
+//Files ending in .gz are treated as gzip compressed CSV
+//An empty file, or one with only a header row, is read as zero transactions and leaves clients unchanged
+pub fn read_csv(filename: String, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    read_csv_reader(open_file(&filename)?, clients, rejected, counts, options)
+}
+
+//Fetches a CSV over HTTP and reads its transactions as read_csv does. An error status from the server
+//is reported as an error rather than being read as CSV. Only built with the url feature
+#[cfg(feature = "url")]
+pub fn read_csv_url(url: &str, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    let response = reqwest::blocking::get(url).and_then(|response| response.error_for_status()).map_err(ProcessError::Http)?;
+    read_csv_reader(response, clients, rejected, counts, options)
+}
+
+//Reads a file as read_csv does while showing a progress bar on stderr, measured in bytes of the file
+//read. For a compressed file the bar follows the compressed bytes. Only built with the progress feature
+#[cfg(feature = "progress")]
+pub fn read_csv_with_progress(filename: &str, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    let file = File::open(filename)?;
+    let style = ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} {elapsed_precise}").unwrap_or_else(|_| ProgressStyle::default_bar());
+    let bar = ProgressBar::new(file.metadata()?.len()).with_style(style);
+    let result = read_csv_reader(decompress(filename, bar.wrap_read(file)), clients, rejected, counts, options);
+    bar.finish_and_clear();
+    result
+}
+
+//Reads from a source of unknown size, such as stdin, as read_csv_reader does while showing a spinner
+//on stderr with the bytes read so far. Only built with the progress feature
+#[cfg(feature = "progress")]
+pub fn read_csv_reader_with_progress<R: Read>(reader: R, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    let style = ProgressStyle::with_template("{spinner} {bytes} {elapsed_precise}").unwrap_or_else(|_| ProgressStyle::default_spinner());
+    let bar = ProgressBar::new_spinner().with_style(style);
+    let result = read_csv_reader(bar.wrap_read(reader), clients, rejected, counts, options);
+    bar.finish_and_clear();
+    result
+}
+
+fn csv_reader<R: Read>(reader: R, options: &ProcessOptions) -> Result<(csv::Reader<R>, Option<csv::StringRecord>), ProcessError> {
+    //Builds the CSV reader along with the header row, if the file has one
+    //With a header row the columns are matched by name, so they can appear in any order
+    //A UTF-8 byte order mark at the start of the data, as written by Excel, is skipped by the reader
+    let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).has_headers(options.has_headers).delimiter(options.delimiter).from_reader(reader);
+    let headers = if options.has_headers { Some(rdr.headers()?.clone()) } else { None };
+    Ok((rdr, headers))
+}
+
+fn deserialize_row<'r>(record: &'r csv::StringRecord, headers: Option<&'r csv::StringRecord>, line: u64, options: &ProcessOptions) -> Result<Row<'r>, ProcessError> {
+    //Reads a record into a row. The CSV error for a client or transaction id that does not fit its
+    //type does not say which field was at fault, so an id out of range is reported by name instead
+    //A transaction type given a different name in the options is replaced by the engine's own name
+    let mut row: Row = record.deserialize(headers).map_err(|err| {
+        for (field, column, max) in [("client", 1, u64::from(u16::MAX)), ("tx", 2, u64::from(u32::MAX))] {
+            let value = match headers {
+                Some(headers) => headers.iter().position(|name| name == field).and_then(|index| record.get(index)),
+                None => record.get(column),
+            };
+            if let Some(value) = value {
+                if value.parse::<i128>().is_ok_and(|id| id < 0 || id > i128::from(max)) {
+                    return ProcessError::IdOutOfRange { line, field, value: value.to_string(), max };
                 }
             }
         }
+        ProcessError::from(err)
+    })?;
+    row.transaction_type = engine_type_name(row.transaction_type, options);
+    Ok(row)
+}
+
+fn engine_type_name<'a>(transaction_type: &'a str, options: &ProcessOptions) -> &'a str {
+    options.type_names.get(transaction_type).map_or(transaction_type, |transaction_type| transaction_type.name())
+}
+
+//Records the client that first used each deposit or withdrawal transaction id, and the line it was
+//used on, so a dispute naming another client's transaction can be told apart from an unknown one
+//Transactions already stored for clients from earlier files are treated as coming before every line
+#[derive(Default)]
+struct TransactionOwners(HashMap<u32, (u16, u64)>);
+
+impl TransactionOwners {
+    fn from_clients(clients: &HashMap<u16, Client>) -> Self {
+        let mut owners = TransactionOwners::default();
+        for client in clients.values() {
+            for transaction_id in &client.transaction_ids {
+                owners.0.entry(*transaction_id).or_insert((client.client_id, 0));
+            }
+        }
+        owners
+    }
+
+    fn add(&mut self, row: &Row, line: u64) {
+        if matches!(row.transaction_type, "deposit" | "withdrawal" | "hold") {
+            self.0.entry(row.transaction_id).or_insert((row.client_id, line));
+        }
+    }
+
+    //The other client that used the transaction id before the given line, if any
+    fn other_owner(&self, row: &Row, line: u64) -> Option<u16> {
+        match self.0.get(&row.transaction_id) {
+            Some(&(owner, owner_line)) if owner != row.client_id && owner_line < line => Some(owner),
+            _ => None,
+        }
+    }
+}
+
+//Reads transactions from any source, such as stdin or a file already opened by the caller
+//Whitespace around fields is trimmed so rows like "deposit, 1, 1, 1.0" are read correctly
+pub fn read_csv_reader<R: Read>(reader: R, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    let (mut rdr, headers) = csv_reader(reader, options)?;
+    if options.chronological {
+        return read_records_chronological(rdr, headers.as_ref(), clients, rejected, counts, options);
+    }
+    if options.threads > 1 {
+        return read_records_parallel(rdr, headers.as_ref(), clients, rejected, counts, options);
+    }
+    let mut owners = TransactionOwners::from_clients(clients);
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map_or(0, |position| position.line());
+        let row = deserialize_row(&record, headers.as_ref(), line, options)?;
+        counts.add(row.transaction_type, options);
+        owners.add(&row, line);
+        process_record(row, line, clients, rejected, &owners, options)?;
+    }
+    Ok(())
+}
+
+//Where a row's timestamp sorts when processing chronologically. Numbers, such as Unix times, are
+//compared by value and anything else as text, which puts ISO 8601 timestamps in order. Rows without
+//a timestamp come first, then numbers, then text
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum TimestampKey<'a> {
+    Missing,
+    Number(Decimal),
+    Text(&'a str)
+}
+
+fn timestamp_key(timestamp: Option<&str>) -> TimestampKey<'_> {
+    match timestamp {
+        Some(text) => text.parse().map_or(TimestampKey::Text(text), TimestampKey::Number),
+        None => TimestampKey::Missing,
+    }
+}
+
+fn read_records_chronological<R: Read>(mut rdr: csv::Reader<R>, headers: Option<&csv::StringRecord>, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    //Reads every row before any is processed, then applies them in timestamp order. Rows with the
+    //same timestamp keep their file order. Lines are still reported as they are in the file
+    let records = rdr.records().collect::<Result<Vec<_>, _>>()?;
+    let mut rows = Vec::with_capacity(records.len());
+    for record in &records {
+        let line = record.position().map_or(0, |position| position.line());
+        let row = deserialize_row(record, headers, line, options)?;
+        counts.add(row.transaction_type, options);
+        rows.push((line, row));
+    }
+    rows.sort_by_key(|(_, row)| timestamp_key(row.timestamp));
+    //Owners are recorded as the rows are processed rather than by line, so any other client found
+    //for a transaction used it earlier in timestamp order, even if on a later line
+    let mut owners = TransactionOwners::from_clients(clients);
+    for (line, row) in rows {
+        owners.add(&row, 0);
+        process_record(row, line, clients, rejected, &owners, options)?;
+    }
+    Ok(())
+}
+
+//A transaction read from a line of JSON, with the same fields as a CSV row. The amount can be given
+//as a string or a number, and left out or null where a CSV row would leave it empty
+#[derive(Deserialize)]
+struct JsonRow {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: u16,
+    tx: u32,
+    #[serde(default)]
+    amount: serde_json::Value,
+    #[serde(default)]
+    timestamp: Option<String>,
+    #[serde(default)]
+    currency: Option<String>
+}
+
+//Reads transactions in the JSON lines format, one object per line such as
+//{"type": "deposit", "client": 1, "tx": 1, "amount": "1.0"}, and processes them exactly as CSV rows
+//Blank lines are skipped. The rows are always processed in order on the calling thread
+pub fn read_jsonl<R: Read>(reader: R, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    let mut owners = TransactionOwners::from_clients(clients);
+    for (index, text) in BufReader::new(reader).lines().enumerate() {
+        let text = text?;
+        let line = index as u64 + 1;
+        //A byte order mark is skipped, as it is for CSV
+        let text = if index == 0 { text.trim_start_matches('\u{feff}') } else { &text };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let json: JsonRow = serde_json::from_str(text).map_err(|error| ProcessError::Json { line, error })?;
+        let amount = match &json.amount {
+            serde_json::Value::String(amount) => amount.trim().to_string(),
+            serde_json::Value::Null => String::new(),
+            amount => amount.to_string(),
+        };
+        let row = Row {
+            transaction_type: engine_type_name(&json.transaction_type, options),
+            client_id: json.client,
+            transaction_id: json.tx,
+            amount: &amount,
+            timestamp: json.timestamp.as_deref(),
+            currency: json.currency.as_deref()
+        };
+        counts.add(row.transaction_type, options);
+        owners.add(&row, line);
+        process_record(row, line, clients, rejected, &owners, options)?;
+    }
+    Ok(())
+}
+
+//Reads a file of transactions in the JSON lines format, decompressing it when it has a .gz extension
+pub fn read_jsonl_file(filename: &str, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    read_jsonl(open_file(filename)?, clients, rejected, counts, options)
+}
+
+//Processes a single CSV row, such as a line typed in by a user, in the order type, client, tx, amount
+//Returns the id of the client the row was for, or None if the line holds no row
+pub fn process_line(text: &str, line: u64, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, options: &ProcessOptions) -> Result<Option<u16>, ProcessError> {
+    let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).has_headers(false).delimiter(options.delimiter).from_reader(text.as_bytes());
+    let record = match rdr.records().next() {
+        Some(result) => result?,
+        None => return Ok(None),
+    };
+    let row = deserialize_row(&record, None, line, options)?;
+    let client_id = row.client_id;
+    let owners = TransactionOwners::from_clients(clients);
+    process_record(row, line, clients, rejected, &owners, options)?;
+    Ok(Some(client_id))
+}
+
+//The rows read for a single client, along with the line each row was read from
+type ClientRecords = Vec<(u64, csv::StringRecord)>;
+
+fn read_records_parallel<R: Read>(mut rdr: csv::Reader<R>, headers: Option<&csv::StringRecord>, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    //Transactions for different clients never interact, so the rows are bucketed by client id and
+    //each client's rows are processed in file order on a worker thread. The whole file is held in
+    //memory while the buckets are built. The owner of each transaction id is found while bucketing,
+    //and the line it was first used on keeps disputes reported the same as when processing serially
+    //If a row fails, the error from the earliest line is returned. Other clients' rows after that
+    //line may already have been processed
+    let mut buckets: HashMap<u16, ClientRecords> = HashMap::new();
+    let mut owners = TransactionOwners::from_clients(clients);
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map_or(0, |position| position.line());
+        let row = deserialize_row(&record, headers, line, options)?;
+        counts.add(row.transaction_type, options);
+        owners.add(&row, line);
+        buckets.entry(row.client_id).or_default().push((line, record));
+    }
+    let work: Vec<(u16, Option<Client>, ClientRecords)> = buckets.into_iter()
+        .map(|(client_id, records)| (client_id, clients.remove(&client_id), records))
+        .collect();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(options.threads).build().map_err(ProcessError::ThreadPool)?;
+    let results: Vec<_> = pool.install(|| work.into_par_iter().map(|(client_id, client, records)| {
+        let mut bucket_clients: HashMap<u16, Client> = HashMap::new();
+        if let Some(client) = client {
+            bucket_clients.insert(client_id, client);
+        }
+        let mut bucket_rejected: Vec<RejectedTransaction> = Vec::new();
+        let mut failure = None;
+        for (line, record) in &records {
+            let result = deserialize_row(record, headers, *line, options)
+                .and_then(|row| process_record(row, *line, &mut bucket_clients, &mut bucket_rejected, &owners, options));
+            if let Err(err) = result {
+                failure = Some((*line, err));
+                break;
+            }
+        }
+        (bucket_clients, bucket_rejected, failure)
+    }).collect());
+    let mut first_failure: Option<(u64, ProcessError)> = None;
+    let mut new_rejected: Vec<RejectedTransaction> = Vec::new();
+    for (bucket_clients, bucket_rejected, failure) in results {
+        clients.extend(bucket_clients);
+        new_rejected.extend(bucket_rejected);
+        if let Some((line, err)) = failure {
+            if first_failure.as_ref().is_none_or(|(first_line, _)| line < *first_line) {
+                first_failure = Some((line, err));
+            }
+        }
+    }
+    //Rejected transactions are kept in file order, as they would be when processing serially
+    new_rejected.sort_by_key(|trans| trans.line);
+    rejected.extend(new_rejected);
+    match first_failure {
+        Some((_, err)) => Err(err),
+        None => Ok(()),
+    }
+}
+
+//The result of processing a file: the client accounts ordered by client id, the number of rows of
+//each transaction type that were read and the transactions that were rejected
+#[derive(Debug)]
+pub struct ProcessReport {
+    pub clients: Vec<Client>,
+    pub counts: TransactionCounts,
+    pub rejected: Vec<RejectedTransaction>
+}
+
+//Checks that no money has been created or lost by processing. For every client the total funds must
+//equal the deposits less the withdrawals, after reversing any part charged back and adding back
+//withdrawals held in dispute, and the held funds must equal the amounts still in dispute
+//The balances are exact decimals, so they must match exactly
+pub fn check_conservation(report: &ProcessReport) -> Result<(), String> {
+    for client in &report.clients {
+        let (expected_total, expected_held) = client.replayed_balances();
+        if client.total() != expected_total {
+            return Err(format!("client {} has a total of {} but its transactions add up to {}", client.client_id, client.total(), expected_total));
+        }
+        if client.held != expected_held {
+            return Err(format!("client {} has {} held but {} is in dispute", client.client_id, client.held, expected_held));
+        }
+    }
+    Ok(())
+}
+
+//Resets a client's available and held funds to the balances its stored transactions add up to, as
+//checked by check_conservation. Useful for repairing a corrupted saved state, or for seeing the effect
+//of a change to the dispute rules on accounts processed earlier
+pub fn recompute_balances(client: &mut Client) {
+    let (total, held) = client.replayed_balances();
+    client.available = total - held;
+    client.held = held;
+    client.total = total;
+}
+
+//Processes every transaction in the CSV and reports the resulting client accounts
+pub fn process_file(filename: &str) -> Result<ProcessReport, ProcessError> {
+    process_file_with(filename, &ProcessOptions::default())
+}
+
+//Same as process_file, with control over how the transactions are processed
+pub fn process_file_with(filename: &str, options: &ProcessOptions) -> Result<ProcessReport, ProcessError> {
+    process_reader(open_file(filename)?, options)
+}
+
+//Processes every transaction read from the reader and reports the resulting client accounts
+pub fn process_reader<R: Read>(reader: R, options: &ProcessOptions) -> Result<ProcessReport, ProcessError> {
+    let mut clients: HashMap<u16, Client> = HashMap::new();
+    let mut rejected: Vec<RejectedTransaction> = Vec::new();
+    let mut counts = TransactionCounts::default();
+    read_csv_reader(reader, &mut clients, &mut rejected, &mut counts, options)?;
+    Ok(ProcessReport { clients: sorted_clients(clients), counts, rejected })
+}
+
+//Processes CSV data already held in memory, such as a string built by the caller, with the default options
+pub fn process_bytes(data: &[u8]) -> Result<ProcessReport, ProcessError> {
+    process_reader(data, &ProcessOptions::default())
+}
+
+//A client account just after a transaction was applied to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionEvent {
+    pub client_id: u16,
+    pub transaction_type: String,
+    pub available: Decimal,
+    pub held: Decimal
+}
+
+//Processes the transactions from a reader one row at a time as it is iterated, yielding an event for
+//each row that changes a client account. Rows that are rejected or discarded yield nothing
+//Iteration stops after the first error
+pub struct TransactionEvents<R: Read> {
+    rdr: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    record: csv::StringRecord,
+    clients: HashMap<u16, Client>,
+    rejected: Vec<RejectedTransaction>,
+    counts: TransactionCounts,
+    owners: TransactionOwners,
+    options: ProcessOptions,
+    finished: bool
+}
+
+//Starts processing the transactions read from the reader. Rows are always processed in file order on
+//the calling thread, whatever the threads and chronological options are set to
+pub fn process_events<R: Read>(reader: R, options: &ProcessOptions) -> Result<TransactionEvents<R>, ProcessError> {
+    let (rdr, headers) = csv_reader(reader, options)?;
+    Ok(TransactionEvents {
+        rdr,
+        headers,
+        record: csv::StringRecord::new(),
+        clients: HashMap::new(),
+        rejected: Vec::new(),
+        counts: TransactionCounts::default(),
+        owners: TransactionOwners::default(),
+        options: options.clone(),
+        finished: false
+    })
+}
+
+impl<R: Read> TransactionEvents<R> {
+    //Processes any rows not yet iterated over and reports the resulting client accounts
+    pub fn into_report(mut self) -> Result<ProcessReport, ProcessError> {
+        for event in &mut self {
+            event?;
+        }
+        Ok(ProcessReport { clients: sorted_clients(self.clients), counts: self.counts, rejected: self.rejected })
+    }
+
+    fn next_row(&mut self) -> Result<Option<TransactionEvent>, ProcessError> {
+        //Processes the next row, returning None if it left every account unchanged or there are no rows left
+        if !self.rdr.read_record(&mut self.record)? {
+            self.finished = true;
+            return Ok(None);
+        }
+        let line = self.record.position().map_or(0, |position| position.line());
+        let row = deserialize_row(&self.record, self.headers.as_ref(), line, &self.options)?;
+        let client_id = row.client_id;
+        let transaction_type = row.transaction_type.to_string();
+        self.counts.add(row.transaction_type, &self.options);
+        self.owners.add(&row, line);
+        if !process_record(row, line, &mut self.clients, &mut self.rejected, &self.owners, &self.options)? {
+            return Ok(None);
+        }
+        Ok(self.clients.get(&client_id).map(|client| TransactionEvent { client_id, transaction_type, available: client.available, held: client.held }))
     }
-    
-    fn process_chargeback(row: Row, client: &mut Client) {
-        //If transaction is in dispute then amount is removed from held funds and account is locked 
-        //Locked accounts can not have any future transactions processed
-        if !client.locked {
-            for trans in client.current_transactions.iter_mut() {
-                if trans.transaction_id == row.transaction_id && trans.in_dispute {
-                    client.locked = true;
-                    client.held -= trans.amount;
+}
+
+impl<R: Read> Iterator for TransactionEvents<R> {
+    type Item = Result<TransactionEvent, ProcessError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            match self.next_row() {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => {}
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
                 }
             }
         }
+        None
+    }
+}
+
+//A row found to be malformed while validating a file, along with the line it was read from
+#[derive(Debug)]
+pub struct MalformedRow {
+    pub line: u64,
+    pub error: ProcessError
+}
+
+//Summarises a file checked by validate_file, counting the well formed rows of each transaction
+//type and listing every malformed row
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub disputes: usize,
+    pub resolves: usize,
+    pub chargebacks: usize,
+    pub malformed: Vec<MalformedRow>
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.malformed.is_empty()
     }
+}
+
+//Checks every row of the CSV without building any client accounts
+pub fn validate_file(filename: &str) -> Result<ValidationReport, ProcessError> {
+    validate_file_with(filename, &ProcessOptions::default())
+}
+
+//Same as validate_file, reading the CSV with the given options
+pub fn validate_file_with(filename: &str, options: &ProcessOptions) -> Result<ValidationReport, ProcessError> {
+    validate_reader(open_file(filename)?, options)
+}
 
-}
\ No newline at end of file
+//Checks that every row read from the reader parses, has a known transaction type and, for deposits
+//and withdrawals, a valid amount. Balances are not computed, so disputes and withdrawals are not
+//checked against the accounts. Only a failure to read the input is returned as an error
+pub fn validate_reader<R: Read>(reader: R, options: &ProcessOptions) -> Result<ValidationReport, ProcessError> {
+    let (mut rdr, headers) = csv_reader(reader, options)?;
+    let mut report = ValidationReport::default();
+    for result in rdr.records() {
+        report.rows += 1;
+        let record = match result {
+            Ok(record) => record,
+            Err(err) if err.is_io_error() => return Err(err.into()),
+            Err(err) => {
+                let line = err.position().map_or(0, |position| position.line());
+                report.malformed.push(MalformedRow { line, error: err.into() });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        let checked = deserialize_row(&record, headers.as_ref(), line, options)
+            .and_then(|row| validate_row(&row, line, options).map(|_| row.transaction_type));
+        match checked {
+            Ok("deposit") => report.deposits += 1,
+            Ok("withdrawal") => report.withdrawals += 1,
+            Ok("dispute") => report.disputes += 1,
+            Ok("resolve") => report.resolves += 1,
+            Ok("chargeback") => report.chargebacks += 1,
+            Ok(_) => {}
+            Err(error) => report.malformed.push(MalformedRow { line, error }),
+        }
+    }
+    Ok(report)
+}
+
+fn validate_row(row: &Row, line: u64, options: &ProcessOptions) -> Result<(), ProcessError> {
+    //Applies the same checks to a row that processing would, without needing any client accounts
+    if !is_known_type(row.transaction_type, options) {
+        return Err(ProcessError::UnknownTransactionType { line, transaction_type: row.transaction_type.to_string() });
+    }
+    //Disputes only have an amount when they are partial
+    if matches!(row.transaction_type, "deposit" | "withdrawal" | "hold") || (row.transaction_type == "dispute" && !row.amount.is_empty()) {
+        let amount = parse_amount(row.transaction_id, row.amount, options)?;
+        is_positive_amount(row.transaction_id, amount, AmountPolicy::Error)?;
+    }
+    Ok(())
+}
+
+//Converts the client accounts built by read_csv into a list ordered by client id
+pub fn sorted_clients(clients: HashMap<u16, Client>) -> Vec<Client> {
+    let mut clients: Vec<Client> = clients.into_values().collect();
+    clients.sort_by_key(|client| client.client_id);
+    clients
+}
+
+//Looks up a client account by id in a list of client accounts
+pub fn find_client(clients: &[Client], client_id: u16) -> Option<&Client> {
+    clients.iter().find(|client| client.client_id == client_id)
+}
+
+//The available and held funds and whether the account is locked for every client, keyed on client id
+//so a service can look up accounts directly. The amounts are exact decimals, as they are everywhere else
+pub fn balances_map(clients: &[Client]) -> HashMap<u16, (Decimal, Decimal, bool)> {
+    clients.iter().map(|client| (client.client_id, (client.available, client.held, client.is_locked()))).collect()
+}
+
+fn parse_amount(transaction_id: u32, value: &str, options: &ProcessOptions) -> Result<Decimal, ProcessError> {
+    //Parses a transaction amount, reporting the transaction and the offending value on failure
+    //Only deposits and withdrawals carry an amount, so it is never parsed for the other transaction types
+    //Scientific notation such as 1e3 and underscores such as 1_000 are accepted by the decimal parser,
+    //and commas separating the thousands such as 1,000.00 are removed first
+    //With a decimal comma set in the options the roles are swapped, so 1.000,50 is read as 1000.5
+    //A currency symbol in front of the amount is removed when one has been set in the options
+    //Amounts are decimals rather than floats, so values like NaN and inf are never parsed as numbers
+    if value.is_empty() {
+        return Err(ProcessError::MissingAmount { transaction_id });
+    }
+    let invalid = || ProcessError::ParseAmount { transaction_id, value: value.to_string() };
+    let unprefixed = match &options.currency_symbol {
+        Some(symbol) => value.strip_prefix(symbol.as_str()).unwrap_or(value),
+        None => value,
+    };
+    let (separator, decimal_point) = if options.decimal_comma { ('.', ',') } else { (',', '.') };
+    let normalized = if unprefixed.contains(separator) {
+        strip_thousands_separators(unprefixed, separator, decimal_point).ok_or_else(invalid)?
+    } else {
+        unprefixed.to_string()
+    };
+    normalized.replace(decimal_point, ".").parse().map_err(|_| invalid())
+}
+
+fn strip_thousands_separators(value: &str, separator: char, decimal_point: char) -> Option<String> {
+    //Removes the separators from the whole number part of an amount, as long as they split it into
+    //groups of three digits. Separators anywhere else, such as a decimal comma in 1,5 when the comma
+    //separates thousands, leave the amount invalid
+    let whole = value.split(decimal_point).next().unwrap_or(value);
+    let digits = whole.trim_start_matches(['-', '+']);
+    let mut groups = digits.split(separator);
+    let first = groups.next()?;
+    let first_valid = (1..=3).contains(&first.len()) && first.bytes().all(|byte| byte.is_ascii_digit());
+    let rest_valid = groups.all(|group| group.len() == 3 && group.bytes().all(|byte| byte.is_ascii_digit()));
+    if !first_valid || !rest_valid || value[whole.len()..].contains(separator) {
+        return None;
+    }
+    Some(value.replace(separator, ""))
+}
+
+fn exceeds_max_amount(amount: Decimal, options: &ProcessOptions) -> Option<RejectReason> {
+    //Checks a deposit or withdrawal amount against the largest amount allowed, if there is one
+    match options.max_amount {
+        Some(max_amount) if amount > max_amount => Some(RejectReason::ExceedsMaxAmount { max_amount }),
+        _ => None,
+    }
+}
+
+fn is_positive_amount(transaction_id: u32, amount: Decimal, amount_policy: AmountPolicy) -> Result<bool, ProcessError> {
+    //Deposits and withdrawals must move a strictly positive amount. Anything else is either
+    //skipped or reported depending on the amount policy
+    if amount > Decimal::ZERO {
+        return Ok(true);
+    }
+    match amount_policy {
+        AmountPolicy::Ignore => Ok(false),
+        AmountPolicy::Error => Err(ProcessError::InvalidAmount { transaction_id, amount }),
+    }
+}
+
+fn process_record(row: Row, line: u64, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, owners: &TransactionOwners, options: &ProcessOptions) -> Result<bool, ProcessError> {
+    //Processes the row, returning whether it changed a client account
+    //Any error from processing the row says which line it was read from
+    apply_record(row, line, clients, rejected, owners, options).map_err(|error| error.at_line(line))
+}
+
+fn apply_record(row: Row, line: u64, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, owners: &TransactionOwners, options: &ProcessOptions) -> Result<bool, ProcessError> {
+    //looks up the current client matching the client id in the row. 
+    //If found, then calls appropriate function the handle the transaction
+    //If no current client found, then creates a new client and processes the transaction
+    //New clients can only be created with an initial deposit transaction, any other
+    //transaction for an unknown client is rejected unless the new client policy allows it
+    //Rows with an unknown transaction type are rejected, or stop processing in strict mode
+    //Rows of a disabled type are skipped without being rejected
+    if is_disabled_type(row.transaction_type, options) {
+        return Ok(false);
+    }
+    if !is_known_type(row.transaction_type, options) {
+        return match options.strictness {
+            Strictness::Lenient => {
+                rejected.push(RejectedTransaction::new(&row, line, RejectReason::UnknownTransactionType));
+                Ok(false)
+            }
+            Strictness::Strict => Err(ProcessError::UnknownTransactionType { line, transaction_type: row.transaction_type.to_string() }),
+        };
+    }
+    let client_id = row.client_id;
+    let transaction_id = row.transaction_id;
+    let transaction_type = row.transaction_type; 
+    let mut new_client = None;
+    let client = match clients.get_mut(&client_id) {
+        Some(client) => client,
+        None => {
+            if transaction_type != "deposit" && options.new_client_policy == NewClientPolicy::DepositOnly {
+                rejected.push(RejectedTransaction::new(&row, line, RejectReason::UnknownClient));
+                return Ok(false);
+            }
+            new_client.insert(Client::new(client_id))
+        }
+    };
+    //A transaction already processed is skipped without being rejected when processing idempotently
+    if options.idempotent && !client.processed_ids.insert((transaction_type.to_string(), transaction_id)) {
+        return Ok(false);
+    }
+    //Once a client has reached the transaction limit every further transaction for it is rejected
+    if let Some(limit) = options.max_transactions_per_client {
+        if client.transactions_processed >= limit {
+            rejected.push(RejectedTransaction::new(&row, line, RejectReason::TransactionLimit { limit }));
+            return Ok(false);
+        }
+        client.transactions_processed += 1;
+    }
+    //Transactions in a different currency from the account are rejected, as balances in two currencies
+    //can not be added together. Rows without a currency are taken to be in the account's currency
+    let currency = row.currency.filter(|currency| !currency.is_empty());
+    if let (Some(currency), Some(account_currency)) = (currency, client.currency.as_deref()) {
+        if currency != account_currency {
+            let reason = RejectReason::CurrencyMismatch { currency: currency.to_string(), account_currency: account_currency.to_string() };
+            rejected.push(RejectedTransaction::new(&row, line, reason));
+            return Ok(false);
+        }
+    }
+    let outcome = run_transaction(client, transaction_type, transaction_id, row.amount, options)?;
+    //A transaction that is not found may have been made by another client
+    let outcome = match outcome {
+        Outcome::Rejected(RejectReason::TransactionNotFound) => match owners.other_owner(&row, line) {
+            Some(owner) => Outcome::Rejected(RejectReason::OtherClientTransaction { owner }),
+            None => Outcome::Rejected(RejectReason::TransactionNotFound),
+        },
+        outcome => outcome,
+    };
+    let applied = outcome == Outcome::Applied;
+    //An account takes the currency of the first transaction that changes it
+    match outcome {
+        Outcome::Rejected(reason) => rejected.push(RejectedTransaction::new(&row, line, reason)),
+        Outcome::Applied if client.currency.is_none() => client.currency = currency.map(str::to_string),
+        Outcome::Applied | Outcome::Discarded => {}
+    }
+    //A deposit that is discarded or rejected does not leave an account behind for a new client,
+    //unless any transaction is allowed to create one
+    if let Some(client) = new_client {
+        if options.new_client_policy == NewClientPolicy::AnyTransaction || !client.current_transactions.is_empty() {
+            clients.insert(client_id, client);
+        }
+    }
+    Ok(applied)
+}
+
+fn log_transaction(row: &Row, client: &Client) {
+    //Traces each change to a client account, shown when running with RUST_LOG=debug
+    debug!("tx {} {}, {}", row.transaction_id, row.transaction_type, client);
+}
+
+fn adjust_balances(available: &mut Decimal, held: &mut Decimal, total: &mut Decimal, available_change: Decimal, held_change: Decimal, transaction_id: u32) -> Result<(), ProcessError> {
+    //Applies a transaction to the balances of a client, keeping its total up to date. If either balance,
+    //or their total, would be too large to represent the balances are left unchanged and an error is returned instead
+    match (available.checked_add(available_change), held.checked_add(held_change)) {
+        (Some(new_available), Some(new_held)) if new_available.checked_add(new_held).is_some() => {
+            *available = new_available;
+            *held = new_held;
+            *total = new_available + new_held;
+            Ok(())
+        }
+        _ => Err(ProcessError::Overflow { transaction_id }),
+    }
+}
+
+/// Applies a single transaction to a client account, as processing a file would apply a row for
+/// that client. The transaction type is one of deposit, withdrawal, dispute, resolve, chargeback,
+/// hold, release or cancel,
+/// or freeze and unfreeze when admin transactions are enabled in the options, and the amount is left empty for resolves, chargebacks and full disputes
+///
+/// Returns the reason the transaction was rejected, if it was. A transaction that is discarded
+/// without a reason, such as a reused transaction id or one of a type disabled in the options,
+/// leaves the account unchanged and returns None
+///
+/// ```
+/// use rust_decimal::Decimal;
+/// use rustcodingtest::{apply_transaction, Client, ProcessOptions, RejectReason};
+///
+/// let options = ProcessOptions::new();
+/// let mut client = Client::new(1);
+/// apply_transaction(&mut client, "deposit", 1, "10.0", &options).unwrap();
+/// apply_transaction(&mut client, "dispute", 1, "", &options).unwrap();
+/// assert_eq!(client.held, Decimal::new(10, 0));
+/// assert_eq!(client.total(), Decimal::new(10, 0));
+///
+/// let reason = apply_transaction(&mut client, "withdrawal", 2, "5.0", &options).unwrap();
+/// assert!(matches!(reason, Some(RejectReason::InsufficientFunds { .. })));
+/// ```
+pub fn apply_transaction(client: &mut Client, transaction_type: &str, transaction_id: u32, amount: &str, options: &ProcessOptions) -> Result<Option<RejectReason>, ProcessError> {
+    match run_transaction(client, transaction_type, transaction_id, amount, options)? {
+        Outcome::Rejected(reason) => Ok(Some(reason)),
+        Outcome::Applied | Outcome::Discarded => Ok(None),
+    }
+}
+
+//What became of a transaction applied to a client account
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    //The account was changed
+    Applied,
+    //The transaction was skipped without a reason, leaving the account unchanged
+    Discarded,
+    Rejected(RejectReason),
+}
+
+fn run_transaction(client: &mut Client, transaction_type: &str, transaction_id: u32, amount: &str, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    if is_disabled_type(transaction_type, options) {
+        return Ok(Outcome::Discarded);
+    }
+    let row = Row { transaction_type, client_id: client.client_id, transaction_id, amount, timestamp: None, currency: None };
+    let outcome = match transaction_type {
+        "deposit" => process_deposit(&row, client, options)?,
+        "withdrawal" => process_withdrawal(&row, client, options)?,
+        "dispute" => process_dispute(&row, client, options)?,
+        "resolve" => process_resolve(&row, client, options)?,
+        "chargeback" => process_chargeback(&row, client, options)?,
+        "hold" => process_hold(&row, client, options)?,
+        "release" => process_release(&row, client)?,
+        "cancel" => process_cancel(&row, client)?,
+        "freeze" if options.admin_transactions => process_freeze(&row, client),
+        "unfreeze" if options.admin_transactions => process_unfreeze(&row, client),
+        _ => match options.strictness {
+            Strictness::Lenient => Outcome::Rejected(RejectReason::UnknownTransactionType),
+            Strictness::Strict => return Err(ProcessError::UnknownTransactionType { line: 0, transaction_type: transaction_type.to_string() }),
+        },
+    };
+    client.debug_assert_balances(options.overdraft_limit);
+    //A transaction that is not found but whose id is taken may have been evicted. A cancelled hold is no
+    //longer stored either, so under a retention limit it is reported the same way
+    let evicted = options.retained_transactions.is_some() && client.transaction_ids.contains(&transaction_id);
+    match outcome {
+        Outcome::Rejected(RejectReason::TransactionNotFound) if evicted => Ok(Outcome::Rejected(RejectReason::TransactionEvicted)),
+        outcome => Ok(outcome),
+    }
+}
+
+//The available and held funds the client would have after a full dispute of the transaction, without
+//changing the client. The dispute follows the default rules, so a dispute that would be discarded, such
+//as on a locked account, leaves the balances as they are. None if the client has no such transaction
+pub fn simulate_dispute(client: &Client, transaction_id: u32) -> Option<(Decimal, Decimal)> {
+    let mut simulated = client.clone();
+    match apply_transaction(&mut simulated, "dispute", transaction_id, "", &ProcessOptions::default()) {
+        Ok(Some(RejectReason::TransactionNotFound | RejectReason::TransactionEvicted)) | Err(_) => None,
+        _ => Some((simulated.available, simulated.held)),
+    }
+}
+
+fn process_freeze(row: &Row, client: &mut Client) -> Outcome {
+    //Freezes an active account pending review without moving any funds. An account already locked,
+    //whether frozen or charged back, is left as it is
+    if client.status != AccountStatus::Active {
+        return Outcome::Discarded;
+    }
+    client.status = AccountStatus::Frozen;
+    log_transaction(row, client);
+    Outcome::Applied
+}
+
+fn process_unfreeze(row: &Row, client: &mut Client) -> Outcome {
+    //Returns a frozen account to active. An account locked by a chargeback stays locked
+    if client.status != AccountStatus::Frozen {
+        return Outcome::Discarded;
+    }
+    client.status = AccountStatus::Active;
+    log_transaction(row, client);
+    Outcome::Applied
+}
+
+fn store_transaction(client: &mut Client, trans: ApprovedTransaction, options: &ProcessOptions) {
+    //Stores a deposit or withdrawal for later disputes. Beyond the retention limit the oldest transaction
+    //that is not in dispute or on hold is evicted, leaving its id in the set so it can not be reused and adding
+    //what it left in the account to the evicted total. The transaction just stored is never evicted
+    client.transaction_ids.insert(trans.transaction_id);
+    client.current_transactions.push(trans);
+    let newest = client.current_transactions.len() - 1;
+    if options.retained_transactions.is_some_and(|retained| client.current_transactions.len() > retained) {
+        if let Some(index) = client.current_transactions[..newest].iter().position(|trans| !trans.in_dispute && !trans.pending) {
+            let evicted = client.current_transactions.remove(index);
+            let charged_back = if evicted.charged_back { evicted.disputed_amount } else { Decimal::ZERO };
+            match evicted.kind {
+                TransactionKind::Deposit => client.evicted_total += evicted.amount - charged_back,
+                TransactionKind::Withdrawal => client.evicted_total -= evicted.amount - charged_back,
+            }
+        }
+    }
+}
+
+fn process_deposit(row: &Row, client: &mut Client, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    //Adds deposit to client account if the account is active, otherwise the deposit is rejected
+    //Deposit transactions and ammounts are stored for reference in case of a future dispute
+    //A deposit reusing the id of a stored transaction is discarded so disputes stay unambiguous
+    //A deposit of more than the maximum amount is rejected
+    if client.is_locked() {
+        return Ok(Outcome::Rejected(RejectReason::AccountLocked { status: client.status }));
+    }
+    if client.transaction_ids.contains(&row.transaction_id) {
+        return Ok(Outcome::Discarded);
+    }
+    let amount = parse_amount(row.transaction_id, row.amount, options)?;
+    if !is_positive_amount(row.transaction_id, amount, options.amount_policy)? {
+        return Ok(Outcome::Discarded);
+    }
+    if let Some(reason) = exceeds_max_amount(amount, options) {
+        return Ok(Outcome::Rejected(reason));
+    }
+    let approved_trans = ApprovedTransaction{
+        transaction_id: row.transaction_id,
+        kind: TransactionKind::Deposit,
+        amount,
+        in_dispute: false,
+        disputed_amount: Decimal::ZERO,
+        charged_back: false,
+        resolved: false,
+        pending: false,
+        amount_raw: row.amount.to_string()
+    };
+    adjust_balances(&mut client.available, &mut client.held, &mut client.total, amount, Decimal::ZERO, row.transaction_id)?;
+    store_transaction(client, approved_trans, options);
+    log_transaction(row, client);
+    Ok(Outcome::Applied)
+}
+
+fn process_withdrawal(row: &Row, client: &mut Client, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    withdraw(row, client, options, false)
+}
+
+fn process_hold(row: &Row, client: &mut Client, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    //Places a withdrawal on hold pending confirmation, moving its amount from available to held
+    //It is checked as any withdrawal is, and completed by a release or returned by a cancel
+    withdraw(row, client, options, true)
+}
+
+fn withdraw(row: &Row, client: &mut Client, options: &ProcessOptions, pending: bool) -> Result<Outcome, ProcessError> {
+    //Withdraws amount from client account if the account is active and has enough available funds
+    //Withdrawal transactions are stored for reference in case of a future dispute
+    //A withdrawal of more than is available is rejected, recording the funds available at the time,
+    //as is a withdrawal of more than the maximum amount or from an account that is not active
+    //Balances are exact decimals, so withdrawing exactly the available funds always leaves zero
+    //An overdraft limit set in the options lets available go down to minus the limit instead of zero
+    //A pending withdrawal keeps its amount in held until it is released or cancelled
+    if client.is_locked() {
+        return Ok(Outcome::Rejected(RejectReason::AccountLocked { status: client.status }));
+    }
+    if client.transaction_ids.contains(&row.transaction_id) {
+        return Ok(Outcome::Discarded);
+    }
+    let amount = parse_amount(row.transaction_id, row.amount, options)?;
+    if !is_positive_amount(row.transaction_id, amount, options.amount_policy)? {
+        return Ok(Outcome::Discarded);
+    }
+    if let Some(reason) = exceeds_max_amount(amount, options) {
+        return Ok(Outcome::Rejected(reason));
+    }
+    //Compared exactly, with no tolerance, as one would let a withdrawal take slightly more than is available
+    //A withdrawal that would take available below the smallest decimal can never be covered
+    if client.available.checked_sub(amount).is_none_or(|remaining| remaining < -options.overdraft_limit) {
+        return Ok(Outcome::Rejected(RejectReason::InsufficientFunds { requested: amount, available: client.available }));
+    }
+    let held_change = if pending { amount } else { Decimal::ZERO };
+    adjust_balances(&mut client.available, &mut client.held, &mut client.total, -amount, held_change, row.transaction_id)?;
+    store_transaction(client, ApprovedTransaction{
+        transaction_id: row.transaction_id,
+        kind: TransactionKind::Withdrawal,
+        amount,
+        in_dispute: false,
+        disputed_amount: Decimal::ZERO,
+        charged_back: false,
+        resolved: false,
+        pending,
+        amount_raw: row.amount.to_string()
+    }, options);
+    log_transaction(row, client);
+    Ok(Outcome::Applied)
+}
+
+fn find_transaction(transactions: &mut [ApprovedTransaction], transaction_id: u32) -> Option<&mut ApprovedTransaction> {
+    //Finds the stored deposit or withdrawal a dispute, resolve or chargeback refers to. Those rows do
+    //not say which kind of transaction they mean, but a deposit or withdrawal reusing the id of any
+    //stored transaction of either kind is discarded, so the id alone always finds the right one
+    transactions.iter_mut().find(|trans| trans.transaction_id == transaction_id)
+}
+
+fn process_dispute(row: &Row, client: &mut Client, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    //Marks a transaction as in dispute pending resolution
+    //A disputed deposit moves its funds from available to held. If the deposit has already been
+    //withdrawn this leaves available negative, as the client owes the disputed amount
+    //A disputed withdrawal puts the withdrawn funds in held, as they may be returned to the client
+    //A dispute with an amount only disputes that much of the transaction, and can be followed by
+    //further disputes up to the transaction amount. Without an amount all of the rest is disputed
+    //If transaction is not found, or a partial dispute is for more than is left to dispute, then dispute
+    //is rejected. If it is already fully in dispute, has been charged back or is a withdrawal still on
+    //hold it is discarded
+    //A transaction that has been resolved can be disputed again unless the options forbid it
+    if client.is_locked() {
+        return Ok(Outcome::Discarded);
+    }
+    let trans = match find_transaction(&mut client.current_transactions, row.transaction_id) {
+        None => return Ok(Outcome::Rejected(RejectReason::TransactionNotFound)),
+        Some(trans) => trans,
+    };
+    if trans.charged_back || trans.pending {
+        return Ok(Outcome::Discarded);
+    }
+    if trans.resolved && !options.allow_redispute {
+        return Ok(Outcome::Rejected(RejectReason::AlreadyResolved));
+    }
+    let undisputed = trans.amount - trans.disputed_amount;
+    let amount = if row.amount.is_empty() {
+        undisputed
+    } else {
+        let requested = parse_amount(row.transaction_id, row.amount, options)?;
+        if !is_positive_amount(row.transaction_id, requested, options.amount_policy)? {
+            return Ok(Outcome::Discarded);
+        }
+        if requested > undisputed {
+            return Ok(Outcome::Rejected(RejectReason::DisputeExceedsAmount { requested, undisputed }));
+        }
+        requested
+    };
+    if amount.is_zero() {
+        return Ok(Outcome::Discarded);
+    }
+    let available_change = if trans.kind == TransactionKind::Deposit { -amount } else { Decimal::ZERO };
+    adjust_balances(&mut client.available, &mut client.held, &mut client.total, available_change, amount, row.transaction_id)?;
+    trans.in_dispute = true;
+    trans.disputed_amount += amount;
+    log_transaction(row, client);
+    Ok(Outcome::Applied)
+}
+
+fn process_release(row: &Row, client: &mut Client) -> Result<Outcome, ProcessError> {
+    //Completes a withdrawal on hold, removing its amount from held. It then stands as any withdrawal
+    //does and can be disputed. Releases on a locked account are discarded, leaving the amount held
+    if client.is_locked() {
+        return Ok(Outcome::Discarded);
+    }
+    match find_transaction(&mut client.current_transactions, row.transaction_id) {
+        None => Ok(Outcome::Rejected(RejectReason::TransactionNotFound)),
+        Some(trans) if !trans.pending => Ok(Outcome::Rejected(RejectReason::NotPending)),
+        Some(trans) => {
+            let amount = trans.amount;
+            adjust_balances(&mut client.available, &mut client.held, &mut client.total, Decimal::ZERO, -amount, row.transaction_id)?;
+            trans.pending = false;
+            log_transaction(row, client);
+            Ok(Outcome::Applied)
+        }
+    }
+}
+
+fn process_cancel(row: &Row, client: &mut Client) -> Result<Outcome, ProcessError> {
+    //Cancels a withdrawal on hold, returning its amount from held to available. The transaction is no
+    //longer stored, but its id stays taken so it can not be reused. Cancels on a locked account are
+    //discarded, leaving the amount held
+    if client.is_locked() {
+        return Ok(Outcome::Discarded);
+    }
+    match find_transaction(&mut client.current_transactions, row.transaction_id) {
+        None => Ok(Outcome::Rejected(RejectReason::TransactionNotFound)),
+        Some(trans) if !trans.pending => Ok(Outcome::Rejected(RejectReason::NotPending)),
+        Some(trans) => {
+            let amount = trans.amount;
+            adjust_balances(&mut client.available, &mut client.held, &mut client.total, amount, -amount, row.transaction_id)?;
+            client.current_transactions.retain(|trans| trans.transaction_id != row.transaction_id);
+            log_transaction(row, client);
+            Ok(Outcome::Applied)
+        }
+    }
+}
+
+fn held_for_dispute(client: &Client, transaction_id: u32) -> Decimal {
+    //The held funds that can be released by closing the dispute of the transaction, which are those not
+    //held for the client's other open disputes
+    let held_for_others = client.current_transactions.iter()
+        .filter(|trans| trans.in_dispute && trans.transaction_id != transaction_id)
+        .fold(Decimal::ZERO, |sum, trans| sum.saturating_add(trans.disputed_amount));
+    client.held.saturating_sub(held_for_others).max(Decimal::ZERO)
+}
+
+fn release_held(row: &Row, held: Decimal, amount: Decimal, strictness: Strictness) -> Result<Decimal, ProcessError> {
+    //The amount released from held funds when a dispute is closed, which is the amount disputed. Held funds
+    //always cover the open disputes unless the balances have been corrupted, such as in a hand edited saved
+    //state. Should they not, only what is held for this dispute is released, so closing one dispute never
+    //uses up the funds held for another, or processing stops in strict mode
+    if amount <= held {
+        return Ok(amount);
+    }
+    match strictness {
+        Strictness::Lenient => {
+            warn!("tx {} {} releases {} but client {} only has {} held for it", row.transaction_id, row.transaction_type, amount, row.client_id, held);
+            Ok(held)
+        }
+        Strictness::Strict => Err(ProcessError::NegativeHeld { transaction_id: row.transaction_id, amount, held }),
+    }
+}
+
+fn process_resolve(row: &Row, client: &mut Client, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    //If transaction is in dispute, marks ar not in dispute and releases the held ammount
+    //A resolved deposit returns the ammount to available, a resolved withdrawal stands as made
+    //If transaction is not found or is not in dispute then resolve is rejected
+    //Resolves on a locked account are discarded unless resolve_locked is set
+    //Only what is released from held is returned to available, so a corrupted balance can not create funds
+    if client.is_locked() && !options.resolve_locked {
+        return Ok(Outcome::Discarded);
+    }
+    let held = held_for_dispute(client, row.transaction_id);
+    match find_transaction(&mut client.current_transactions, row.transaction_id) {
+        None => Ok(Outcome::Rejected(RejectReason::TransactionNotFound)),
+        Some(trans) if !trans.in_dispute => Ok(Outcome::Rejected(RejectReason::NotDisputed)),
+        Some(trans) => {
+            let released = release_held(row, held, trans.disputed_amount, options.strictness)?;
+            let available_change = if trans.kind == TransactionKind::Deposit { released } else { Decimal::ZERO };
+            adjust_balances(&mut client.available, &mut client.held, &mut client.total, available_change, -released, row.transaction_id)?;
+            trans.in_dispute = false;
+            trans.disputed_amount = Decimal::ZERO;
+            trans.resolved = true;
+            log_transaction(row, client);
+            Ok(Outcome::Applied)
+        }
+    }
+}
+
+fn process_chargeback(row: &Row, client: &mut Client, options: &ProcessOptions) -> Result<Outcome, ProcessError> {
+    //If transaction is in dispute then the disputed amount is removed from held funds and the dispute is closed
+    //A charged back withdrawal is reversed, so its amount is returned to available
+    //The account is closed as charged back when the chargeback policy allows no more chargebacks
+    //Locked accounts can not have any future transactions processed
+    //If transaction is not found or is not in dispute then chargeback is rejected
+    if client.is_locked() {
+        return Ok(Outcome::Discarded);
+    }
+    let held = held_for_dispute(client, row.transaction_id);
+    match find_transaction(&mut client.current_transactions, row.transaction_id) {
+        None => Ok(Outcome::Rejected(RejectReason::TransactionNotFound)),
+        Some(trans) if !trans.in_dispute => Ok(Outcome::Rejected(RejectReason::NotDisputed)),
+        Some(trans) => {
+            let released = release_held(row, held, trans.disputed_amount, options.strictness)?;
+            let available_change = if trans.kind == TransactionKind::Withdrawal { released } else { Decimal::ZERO };
+            adjust_balances(&mut client.available, &mut client.held, &mut client.total, available_change, -released, row.transaction_id)?;
+            trans.in_dispute = false;
+            trans.charged_back = true;
+            client.chargebacks = client.chargebacks.saturating_add(1);
+            if options.chargeback_policy.locks_after(client.chargebacks) {
+                client.status = AccountStatus::ChargedBack;
+                if let Some(on_lock) = &options.on_lock {
+                    on_lock.call(client.client_id, row.transaction_id);
+                }
+            }
+            log_transaction(row, client);
+            Ok(Outcome::Applied)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(data: &str, options: &ProcessOptions) -> Result<ProcessReport, ProcessError> {
+        process_reader(data.as_bytes(), options)
+    }
+
+    fn process_after_state(state: &str, filename: &str, options: &ProcessOptions) -> Result<ProcessReport, ProcessError> {
+        //Processes the file starting from the clients saved in the state, as --load-state does
+        let mut clients: HashMap<u16, Client> = load_state(state).unwrap().into_iter().map(|client| (client.client_id, client)).collect();
+        let mut rejected: Vec<RejectedTransaction> = Vec::new();
+        let mut counts = TransactionCounts::default();
+        read_csv(filename.to_string(), &mut clients, &mut rejected, &mut counts, options)?;
+        Ok(ProcessReport { clients: sorted_clients(clients), counts, rejected })
+    }
+
+    #[test]
+    fn zero_and_negative_amounts_are_skipped_when_ignored() {
+        let data = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,0\ndeposit,1,3,-5.0\nwithdrawal,1,4,0.0\nwithdrawal,1,5,-2.5\n";
+        let report = process(data, &ProcessOptions::new().amount_policy(AmountPolicy::Ignore)).unwrap();
+        let client = &report.clients[0];
+        assert_eq!(client.available, Decimal::new(10, 0));
+        assert_eq!(client.current_transactions.len(), 1);
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn zero_and_negative_amounts_are_errors_when_reported() {
+        let options = ProcessOptions::new().amount_policy(AmountPolicy::Error);
+        for row in ["deposit,1,2,0", "deposit,1,2,-5.0", "withdrawal,1,2,0.0", "withdrawal,1,2,-2.5"] {
+            let data = format!("type,client,tx,amount\ndeposit,1,1,10.0\n{}\n", row);
+            let err = process(&data, &options).unwrap_err();
+            assert!(matches!(err, ProcessError::Line { line: 3, ref error } if matches!(**error, ProcessError::InvalidAmount { transaction_id: 2, .. })), "{}: {}", row, err);
+        }
+    }
+
+    #[test]
+    fn negative_overdraft_limit_allows_no_overdraft() {
+        let options = ProcessOptions::new().overdraft_limit(Decimal::new(-5, 0));
+        let mut client = Client::new(1);
+        assert_eq!(apply_transaction(&mut client, "deposit", 1, "1", &options).unwrap(), None);
+        let reason = apply_transaction(&mut client, "withdrawal", 2, "2", &options).unwrap();
+        assert!(matches!(reason, Some(RejectReason::InsufficientFunds { .. })));
+        assert_eq!(client.available, Decimal::ONE);
+    }
+
+    #[test]
+    fn deserialized_client_has_its_total() {
+        let clients: Vec<Client> = serde_json::from_str(include_str!("../transactions_state.json")).unwrap();
+        assert_eq!(clients[0].total(), Decimal::new(35, 0));
+    }
+
+    #[test]
+    fn transaction_saved_before_partial_disputes_is_disputed_in_full() {
+        let json = r#"[
+            {"transaction_id": 1, "kind": "Deposit", "amount": "10.0", "in_dispute": true, "charged_back": false},
+            {"transaction_id": 2, "kind": "Deposit", "amount": "4.0", "in_dispute": false, "charged_back": true},
+            {"transaction_id": 3, "kind": "Withdrawal", "amount": "2.0", "in_dispute": false, "charged_back": false}
+        ]"#;
+        let transactions: Vec<ApprovedTransaction> = serde_json::from_str(json).unwrap();
+        let disputed: Vec<Decimal> = transactions.iter().map(|trans| trans.disputed_amount).collect();
+        assert_eq!(disputed, [Decimal::new(10, 0), Decimal::new(4, 0), Decimal::ZERO]);
+    }
+
+    #[test]
+    fn state_saved_in_older_formats_still_loads() {
+        for path in ["transactions_state_original.json", "transactions_state_locked_flag.json"] {
+            let clients = load_state(path).unwrap();
+            assert_eq!(clients[0].status, AccountStatus::Active, "{}", path);
+            assert_eq!(clients[0].current_transactions[1].disputed_amount, Decimal::new(25, 0), "{}", path);
+        }
+    }
+
+    #[test]
+    fn locked_flag_loads_as_charged_back() {
+        let json = r#"[{"client_id": 1, "current_transactions": [], "transaction_ids": [], "available": "0", "held": "0", "locked": true, "chargebacks": 1}]"#;
+        let clients: Vec<Client> = serde_json::from_str(json).unwrap();
+        assert_eq!(clients[0].status, AccountStatus::ChargedBack);
+    }
+
+    #[test]
+    fn total_follows_a_dispute_and_resolve() {
+        let options = ProcessOptions::new();
+        let mut client = Client::new(1);
+        for (transaction_type, transaction_id, amount) in [("deposit", 1, "10.0"), ("deposit", 2, "5.0"), ("dispute", 1, ""), ("withdrawal", 3, "2.0"), ("resolve", 1, "")] {
+            apply_transaction(&mut client, transaction_type, transaction_id, amount, &options).unwrap();
+            assert_eq!(client.total(), client.available + client.held);
+        }
+        assert_eq!(client.total(), Decimal::new(13, 0));
+    }
+
+    #[test]
+    fn events_are_only_yielded_for_changed_accounts() {
+        let data = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,1,5.0\ndeposit,1,2,0\nhold,1,3,1.0\ndispute,1,1,\nchargeback,1,1,\ndispute,1,1,\n";
+        let options = ProcessOptions::new().amount_policy(AmountPolicy::Ignore).disabled_types(vec![TransactionType::Hold]);
+        let events: Vec<TransactionEvent> = process_events(data.as_bytes(), &options).unwrap().collect::<Result<_, _>>().unwrap();
+        let types: Vec<&str> = events.iter().map(|event| event.transaction_type.as_str()).collect();
+        assert_eq!(types, ["deposit", "dispute", "chargeback"]);
+    }
+
+    #[test]
+    fn discarded_transaction_does_not_set_the_currency() {
+        let data = "type,client,tx,amount,currency\ndeposit,1,1,10.0,\ndeposit,1,1,5.0,EUR\ndeposit,1,2,0,EUR\ndeposit,1,3,1.0,USD\n";
+        let report = process(data, &ProcessOptions::new().amount_policy(AmountPolicy::Ignore)).unwrap();
+        let client = &report.clients[0];
+        assert_eq!(client.currency.as_deref(), Some("USD"));
+        assert_eq!(client.available, Decimal::new(11, 0));
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn evicted_transactions_can_not_be_disputed_or_reused() {
+        let data = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,20.0\ndeposit,1,3,5.0\ndispute,1,1,\ndeposit,1,1,7.0\ndispute,1,3,\n";
+        let report = process(data, &ProcessOptions::new().retain_transactions(2)).unwrap();
+        let client = &report.clients[0];
+        assert_eq!(client.current_transactions.len(), 2);
+        assert_eq!(client.available, Decimal::new(30, 0));
+        assert_eq!(client.held, Decimal::new(5, 0));
+        assert_eq!(client.replayed_balances(), (client.total(), client.held));
+        let reasons: Vec<&RejectReason> = report.rejected.iter().map(|rejected| &rejected.reason).collect();
+        assert_eq!(reasons, [&RejectReason::TransactionEvicted]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one transaction must be retained")]
+    fn retaining_no_transactions_is_rejected() {
+        let _ = ProcessOptions::new().retain_transactions(0);
+    }
+
+    #[test]
+    fn on_lock_is_called_once_for_each_locked_account() {
+        //Each client is charged back three times, locking on the second, so the third is discarded
+        let mut data = String::from("type,client,tx,amount\n");
+        for client in 1..=8u32 {
+            for tx in client * 10..client * 10 + 3 {
+                data.push_str(&format!("deposit,{client},{tx},1.0\ndispute,{client},{tx},\nchargeback,{client},{tx},\n"));
+            }
+        }
+        for threads in [1, 4] {
+            let locks = Arc::new(Mutex::new(Vec::new()));
+            let recorded = Arc::clone(&locks);
+            let options = ProcessOptions::new()
+                .chargeback_policy(ChargebackPolicy::AfterCount(2))
+                .threads(threads)
+                .on_lock(move |client_id, transaction_id| recorded.lock().unwrap().push((client_id, transaction_id)));
+            process(&data, &options).unwrap();
+            let mut locks = locks.lock().unwrap().clone();
+            locks.sort();
+            let expected: Vec<(u16, u32)> = (1..=8u16).map(|client| (client, u32::from(client) * 10 + 1)).collect();
+            assert_eq!(locks, expected, "threads({})", threads);
+        }
+    }
+
+    #[test]
+    fn process_bytes_matches_processing_the_file() {
+        let from_bytes = process_bytes(include_bytes!("../transactions.csv")).unwrap();
+        let from_file = process_file("transactions.csv").unwrap();
+        assert_eq!(balances_map(&from_bytes.clients), balances_map(&from_file.clients));
+        let rejected = |report: &ProcessReport| report.rejected.iter().map(|rejected| (rejected.line, rejected.reason.clone())).collect::<Vec<_>>();
+        assert_eq!(rejected(&from_bytes), rejected(&from_file));
+
+        let report = process_bytes(b"type,client,tx,amount\ndeposit,2,1,3.5\nwithdrawal,2,2,1.25\n").unwrap();
+        assert_eq!(balances_map(&report.clients)[&2], (Decimal::new(225, 2), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn saved_state_loads_back_the_same_clients() {
+        let report = process_file("transactions.csv").unwrap();
+        let path = std::env::temp_dir().join(format!("rustcodingtest_state_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_state(&report.clients, path).unwrap();
+        let loaded = load_state(path);
+        std::fs::remove_file(path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(balances_map(&loaded), balances_map(&report.clients));
+        for (saved, loaded) in report.clients.iter().zip(&loaded) {
+            assert_eq!(loaded.client_id, saved.client_id);
+            assert_eq!(loaded.status, saved.status);
+            assert_eq!(loaded.total(), saved.total());
+            assert_eq!(loaded.transaction_ids, saved.transaction_ids);
+            assert_eq!(serde_json::to_string(&loaded.current_transactions).unwrap(), serde_json::to_string(&saved.current_transactions).unwrap());
+        }
+    }
+
+    #[test]
+    fn simulated_dispute_matches_a_real_dispute() {
+        let options = ProcessOptions::new();
+        let mut client = Client::new(1);
+        apply_transaction(&mut client, "deposit", 1, "10.0", &options).unwrap();
+        apply_transaction(&mut client, "withdrawal", 2, "4.0", &options).unwrap();
+        for transaction_id in [1, 2] {
+            let simulated = simulate_dispute(&client, transaction_id);
+            assert_eq!(client.available, Decimal::new(6, 0));
+            assert_eq!(client.held, Decimal::ZERO);
+            let mut disputed = client.clone();
+            apply_transaction(&mut disputed, "dispute", transaction_id, "", &options).unwrap();
+            assert_eq!(simulated, Some((disputed.available, disputed.held)), "tx {}", transaction_id);
+        }
+        assert_eq!(simulate_dispute(&client, 3), None);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_output_reads_back_the_summaries() {
+        let report = process_file("transactions_currencies.csv").unwrap();
+        let options = OutputOptions { format: OutputFormat::Bincode, ..OutputOptions::default() };
+        let mut written = Vec::new();
+        write_output(&report.clients, &mut written, &options).unwrap();
+        let read = read_bincode_output(written.as_slice()).unwrap();
+        let fields = |summary: &AccountSummary| (summary.client, summary.available, summary.held, summary.total, summary.status, summary.overdrawn, summary.currency.clone());
+        let expected: Vec<_> = report.clients.iter().map(|client| fields(&AccountSummary::from(client))).collect();
+        assert_eq!(read.iter().map(fields).collect::<Vec<_>>(), expected);
+        assert!(read.iter().all(|summary| summary.currency.is_some()));
+    }
+
+    #[test]
+    fn parallel_processing_matches_serial_processing() {
+        //A fixed linear congruential generator builds the same mix of transactions, including reused
+        //ids and disputes of other clients' transactions, on every run
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % bound
+        };
+        let types = ["deposit", "deposit", "deposit", "withdrawal", "withdrawal", "dispute", "resolve", "chargeback"];
+        let mut data = String::from("type,client,tx,amount\n");
+        for _ in 0..5000 {
+            let transaction_type = types[next(types.len() as u64) as usize];
+            let amount = match transaction_type {
+                "deposit" | "withdrawal" => format!("{}.{:04}", next(100), next(10_000)),
+                _ => String::new(),
+            };
+            data.push_str(&format!("{},{},{},{}\n", transaction_type, next(40) + 1, next(2000) + 1, amount));
+        }
+        let serial = process(&data, &ProcessOptions::new()).unwrap();
+        let parallel = process(&data, &ProcessOptions::new().threads(4)).unwrap();
+        let accounts = |report: &ProcessReport| report.clients.iter().map(|client| (client.client_id, client.available, client.held, client.total(), client.status)).collect::<Vec<_>>();
+        let rejected = |report: &ProcessReport| report.rejected.iter().map(|rejected| (rejected.line, rejected.client_id, rejected.transaction_id, rejected.reason.clone())).collect::<Vec<_>>();
+        assert_eq!(accounts(&parallel), accounts(&serial));
+        assert_eq!(rejected(&parallel), rejected(&serial));
+        assert!(!serial.rejected.is_empty());
+        assert!(serial.rejected.iter().any(|rejected| matches!(rejected.reason, RejectReason::OtherClientTransaction { .. })));
+    }
+
+    #[test]
+    fn withdrawal_within_the_overdraft_limit_is_applied() {
+        let options = ProcessOptions::new().overdraft_limit(Decimal::new(50, 0));
+        let report = process("type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,60.0\n", &options).unwrap();
+        assert_eq!(report.clients[0].available, Decimal::new(-50, 0));
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn withdrawal_beyond_the_overdraft_limit_is_rejected() {
+        let options = ProcessOptions::new().overdraft_limit(Decimal::new(50, 0));
+        let report = process("type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,60.01\n", &options).unwrap();
+        assert_eq!(report.clients[0].available, Decimal::new(10, 0));
+        assert_eq!(report.rejected[0].reason, RejectReason::InsufficientFunds { requested: Decimal::new(6001, 2), available: Decimal::new(10, 0) });
+    }
+
+    #[test]
+    fn withdrawal_from_the_smallest_available_does_not_overflow() {
+        let data = "type,client,tx,amount\ndeposit,1,1,79228162514264337593543950335\nwithdrawal,1,2,79228162514264337593543950335\ndispute,1,1,\nwithdrawal,1,3,1\n";
+        let report = process(data, &ProcessOptions::new()).unwrap();
+        assert_eq!(report.clients[0].available, -Decimal::MAX);
+        assert!(matches!(report.rejected[0].reason, RejectReason::InsufficientFunds { .. }));
+    }
+
+    #[test]
+    fn largest_amounts_through_process_line_do_not_panic() {
+        //The input found to overflow the overdraft check, under options the fuzz target can pick
+        let lines = ["deposit,1,1,79228162514264337593543950335", "withdrawal,1,2,79228162514264337593543950335", "dispute,1,1,", "withdrawal,1,3,1"];
+        let option_sets = [
+            ProcessOptions::new(),
+            ProcessOptions::new().overdraft_limit(Decimal::MAX),
+            ProcessOptions::new().retain_transactions(1).overdraft_limit(Decimal::new(100, 0)),
+        ];
+        for options in &option_sets {
+            let mut clients: HashMap<u16, Client> = HashMap::new();
+            let mut rejected: Vec<RejectedTransaction> = Vec::new();
+            for (index, line) in lines.iter().enumerate() {
+                let result = process_line(line, index as u64 + 1, &mut clients, &mut rejected, options);
+                assert!(matches!(result, Ok(Some(1)) | Err(_)), "{}: {:?}", line, result);
+            }
+        }
+    }
+
+    #[test]
+    fn closing_disputes_on_corrupted_held_funds_creates_no_money() {
+        //The saved state holds 5 for a dispute of 25. Resolving it releases only those 5, leaving the 10
+        //held for the dispute made the next day for its chargeback
+        let report = process_after_state("transactions_state_negative_held.json", "transactions_state_next_day.csv", &ProcessOptions::new()).unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held, client.total()), (Decimal::new(30, 0), Decimal::ZERO, Decimal::new(30, 0)));
+        assert_eq!(client.status, AccountStatus::ChargedBack);
+
+        let options = ProcessOptions::new().strictness(Strictness::Strict);
+        let err = process_after_state("transactions_state_negative_held.json", "transactions_state_next_day.csv", &options).unwrap_err();
+        assert!(matches!(err, ProcessError::Line { line: 3, ref error } if matches!(**error, ProcessError::NegativeHeld { transaction_id: 2, .. })), "{}", err);
+    }
+
+    #[test]
+    fn client_displays_as_a_one_line_summary() {
+        let options = ProcessOptions::new();
+        let mut client = Client::new(1);
+        apply_transaction(&mut client, "deposit", 1, "10", &options).unwrap();
+        assert_eq!(client.to_string(), "client 1: available 10, held 0, total 10, active");
+        apply_transaction(&mut client, "dispute", 1, "4", &options).unwrap();
+        apply_transaction(&mut client, "chargeback", 1, "", &options).unwrap();
+        assert_eq!(client.to_string(), "client 1: available 6, held 0, total 6, charged_back");
+    }
+
+    #[test]
+    fn repeated_deposit_id_is_ignored() {
+        let report = process_bytes(b"type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,1,5.0\n").unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held, client.total()), (Decimal::new(10, 0), Decimal::ZERO, Decimal::new(10, 0)));
+        assert_eq!(client.current_transactions.len(), 1);
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn disputed_withdrawals_are_resolved_or_charged_back() {
+        let file = File::open("transactions_withdrawal_disputes.csv").unwrap();
+        let events: Vec<TransactionEvent> = process_events(file, &ProcessOptions::new()).unwrap().collect::<Result<_, _>>().unwrap();
+        let steps: Vec<(u16, &str, Decimal, Decimal)> = events.iter().map(|event| (event.client_id, event.transaction_type.as_str(), event.available, event.held)).collect();
+        let amount = |amount: i64| Decimal::new(amount, 0);
+        assert_eq!(steps, [
+            (1, "deposit", amount(10), amount(0)),
+            (1, "withdrawal", amount(6), amount(0)),
+            (1, "dispute", amount(6), amount(4)),
+            (1, "resolve", amount(6), amount(0)),
+            (2, "deposit", amount(20), amount(0)),
+            (2, "withdrawal", amount(15), amount(0)),
+            (2, "dispute", amount(15), amount(5)),
+            (2, "chargeback", amount(20), amount(0)),
+        ]);
+    }
+
+    #[test]
+    fn withdrawal_before_any_deposit_is_rejected_as_unknown_client() {
+        let report = process_bytes(b"type,client,tx,amount\nwithdrawal,1,1,5.0\n").unwrap();
+        assert!(report.clients.is_empty());
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!((report.rejected[0].line, report.rejected[0].reason.clone()), (2, RejectReason::UnknownClient));
+    }
+
+    #[test]
+    fn second_dispute_of_a_deposit_holds_nothing_more() {
+        let report = process_bytes(b"type,client,tx,amount\ndeposit,1,1,10.0\ndispute,1,1,\ndispute,1,1,\n").unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held), (Decimal::ZERO, Decimal::new(10, 0)));
+        assert_eq!(client.current_transactions[0].disputed_amount, Decimal::new(10, 0));
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn unknown_type_is_rejected_when_lenient() {
+        let report = process("type,client,tx,amount\ndeposit,1,1,10.0\nbogus,1,2,1.0\n", &ProcessOptions::new()).unwrap();
+        assert_eq!(report.clients[0].available, Decimal::new(10, 0));
+        assert_eq!((report.rejected[0].line, report.rejected[0].reason.clone()), (3, RejectReason::UnknownTransactionType));
+    }
+
+    #[test]
+    fn unknown_type_stops_processing_when_strict() {
+        let options = ProcessOptions::new().strictness(Strictness::Strict);
+        let err = process("type,client,tx,amount\ndeposit,1,1,10.0\nbogus,1,2,1.0\n", &options).unwrap_err();
+        assert!(matches!(err, ProcessError::UnknownTransactionType { line: 3, ref transaction_type } if transaction_type == "bogus"), "{}", err);
+    }
+
+    #[test]
+    fn spaced_fields_are_trimmed() {
+        let report = process_file("transactions_spaced.csv").unwrap();
+        assert_eq!(balances_map(&report.clients), HashMap::from([
+            (1, (Decimal::new(5, 1), Decimal::ONE, false)),
+            (2, (Decimal::new(2, 0), Decimal::ZERO, false)),
+        ]));
+        let reasons: Vec<(u64, &RejectReason)> = report.rejected.iter().map(|rejected| (rejected.line, &rejected.reason)).collect();
+        assert_eq!(reasons, [(6, &RejectReason::InsufficientFunds { requested: Decimal::new(3, 0), available: Decimal::new(2, 0) })]);
+    }
+
+    #[test]
+    fn deposit_without_an_amount_is_an_error() {
+        let err = process_bytes(b"type,client,tx,amount\ndeposit,1,1,\n").unwrap_err();
+        assert!(matches!(err, ProcessError::Line { line: 2, ref error } if matches!(**error, ProcessError::MissingAmount { transaction_id: 1 })), "{}", err);
+    }
+
+    #[test]
+    fn dispute_without_an_amount_is_accepted() {
+        let report = process_bytes(b"type,client,tx,amount\ndeposit,1,1,2.5\ndispute,1,1,\n").unwrap();
+        assert_eq!((report.clients[0].available, report.clients[0].held), (Decimal::ZERO, Decimal::new(25, 1)));
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn dispute_of_a_withdrawn_deposit_overdraws_the_account() {
+        let report = process_file("transactions_overdrawn_dispute.csv").unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held), (Decimal::new(-100, 0), Decimal::new(100, 0)));
+        assert!(client.is_overdrawn());
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn resolve_and_chargeback_tell_not_disputed_from_not_found() {
+        let options = ProcessOptions::new();
+        for transaction_type in ["resolve", "chargeback"] {
+            let mut client = Client::new(1);
+            apply_transaction(&mut client, "deposit", 1, "10.0", &options).unwrap();
+            assert_eq!(apply_transaction(&mut client, transaction_type, 1, "", &options).unwrap(), Some(RejectReason::NotDisputed), "{}", transaction_type);
+            assert_eq!(apply_transaction(&mut client, transaction_type, 2, "", &options).unwrap(), Some(RejectReason::TransactionNotFound), "{}", transaction_type);
+            assert_eq!((client.available, client.held), (Decimal::new(10, 0), Decimal::ZERO));
+        }
+    }
+
+    #[test]
+    fn shuffled_columns_match_the_same_rows_in_header_order() {
+        let shuffled = process_file("transactions_shuffled_columns.csv").unwrap();
+        let ordered = process_bytes(b"type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,20.0\nwithdrawal,1,3,4.0\ndispute,2,2,\ndispute,1,3,\nchargeback,2,2,\n").unwrap();
+        assert_eq!(balances_map(&shuffled.clients), balances_map(&ordered.clients));
+        assert_eq!(shuffled.clients[1].status, AccountStatus::ChargedBack);
+        assert_eq!(shuffled.clients[0].held, Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn resolving_one_of_two_disputes_leaves_the_other_held() {
+        let report = process_file("transactions_overlapping_disputes.csv").unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held), (Decimal::new(10, 0), Decimal::new(25, 0)));
+    }
+
+    #[test]
+    fn empty_and_header_only_files_have_no_clients() {
+        for filename in ["transactions_empty.csv", "transactions_header_only.csv"] {
+            let report = process_file(filename).unwrap();
+            assert!(report.clients.is_empty(), "{}", filename);
+            assert!(report.rejected.is_empty(), "{}", filename);
+            assert_eq!(report.counts.rows, 0, "{}", filename);
+        }
+    }
+
+    #[test]
+    fn chargeback_policy_decides_when_the_account_locks() {
+        let policies = [
+            (ChargebackPolicy::Always, Decimal::new(75, 1), AccountStatus::ChargedBack),
+            (ChargebackPolicy::Never, Decimal::new(35, 1), AccountStatus::Active),
+            (ChargebackPolicy::AfterCount(2), Decimal::new(25, 1), AccountStatus::ChargedBack),
+        ];
+        for (policy, available, status) in policies {
+            let report = process_file_with("transactions_repeated_chargebacks.csv", &ProcessOptions::new().chargeback_policy(policy)).unwrap();
+            let client = &report.clients[0];
+            assert_eq!((client.available, client.held, client.status), (available, Decimal::ZERO, status), "{:?}", policy);
+            let deposit_rejected = report.rejected.iter().any(|rejected| rejected.transaction_id == 4 && matches!(rejected.reason, RejectReason::AccountLocked { .. }));
+            assert_eq!(deposit_rejected, status == AccountStatus::ChargedBack, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn overdrawing_withdrawal_is_rejected_with_the_funds_available() {
+        let report = process_bytes(b"type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,10.5\n").unwrap();
+        assert_eq!(report.clients[0].available, Decimal::new(10, 0));
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].reason, RejectReason::InsufficientFunds { requested: Decimal::new(105, 1), available: Decimal::new(10, 0) });
+    }
+
+    #[test]
+    fn ids_too_large_for_their_field_are_errors() {
+        let err = process_file("transactions_client_id_overflow.csv").unwrap_err();
+        assert!(matches!(err, ProcessError::IdOutOfRange { field: "client", line: 3, ref value, max } if value == "70000" && max == u64::from(u16::MAX)), "{}", err);
+        let err = process_file("transactions_transaction_id_overflow.csv").unwrap_err();
+        assert!(matches!(err, ProcessError::IdOutOfRange { field: "tx", line: 3, ref value, max } if value == "4294967296" && max == u64::from(u32::MAX)), "{}", err);
+    }
+
+    #[test]
+    fn tab_delimited_file_is_read_with_a_tab_delimiter() {
+        let report = process_file_with("transactions_tab_delimited.csv", &ProcessOptions::new().delimiter(b'\t')).unwrap();
+        assert_eq!(balances_map(&report.clients), HashMap::from([
+            (1, (Decimal::ONE, Decimal::ZERO, false)),
+            (2, (Decimal::ZERO, Decimal::new(2, 0), false)),
+        ]));
+        assert!(process_file("transactions_tab_delimited.csv").is_err());
+    }
+
+    #[test]
+    fn dispute_of_another_clients_transaction_names_its_owner() {
+        let report = process_file("transactions_cross_client_dispute.csv").unwrap();
+        let reasons: Vec<(u64, RejectReason)> = report.rejected.iter().map(|rejected| (rejected.line, rejected.reason.clone())).collect();
+        assert_eq!(reasons, [
+            (4, RejectReason::OtherClientTransaction { owner: 1 }),
+            (5, RejectReason::TransactionNotFound),
+            (7, RejectReason::OtherClientTransaction { owner: 2 }),
+            (8, RejectReason::OtherClientTransaction { owner: 1 }),
+        ]);
+        assert_eq!(report.clients[0].held, Decimal::ZERO);
+    }
+
+    #[test]
+    fn half_disputed_deposit_is_resolved() {
+        let options = ProcessOptions::new();
+        let mut client = Client::new(1);
+        apply_transaction(&mut client, "deposit", 1, "10.0", &options).unwrap();
+        assert_eq!(apply_transaction(&mut client, "dispute", 1, "5.0", &options).unwrap(), None);
+        assert_eq!((client.available, client.held), (Decimal::new(5, 0), Decimal::new(5, 0)));
+        assert_eq!(apply_transaction(&mut client, "resolve", 1, "", &options).unwrap(), None);
+        assert_eq!((client.available, client.held), (Decimal::new(10, 0), Decimal::ZERO));
+        assert_eq!(client.current_transactions[0].disputed_amount, Decimal::ZERO);
+    }
+
+    #[test]
+    fn partial_dispute_for_more_than_is_undisputed_is_rejected() {
+        let options = ProcessOptions::new();
+        let mut client = Client::new(1);
+        apply_transaction(&mut client, "deposit", 1, "10.0", &options).unwrap();
+        apply_transaction(&mut client, "dispute", 1, "6.0", &options).unwrap();
+        let reason = apply_transaction(&mut client, "dispute", 1, "5.0", &options).unwrap();
+        assert_eq!(reason, Some(RejectReason::DisputeExceedsAmount { requested: Decimal::new(5, 0), undisputed: Decimal::new(4, 0) }));
+        assert_eq!((client.available, client.held), (Decimal::new(4, 0), Decimal::new(6, 0)));
+    }
+
+    #[test]
+    fn locked_account_keeps_its_disputes_open_by_default() {
+        let report = process_file("transactions_locked_resolve.csv").unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held, client.status), (Decimal::ZERO, Decimal::new(4, 0), AccountStatus::ChargedBack));
+        assert!(report.rejected.is_empty());
+    }
+
+    #[test]
+    fn locked_account_resolves_its_disputes_with_resolve_locked() {
+        let report = process_file_with("transactions_locked_resolve.csv", &ProcessOptions::new().resolve_locked(true)).unwrap();
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held, client.status), (Decimal::new(4, 0), Decimal::ZERO, AccountStatus::ChargedBack));
+    }
+
+    #[test]
+    fn clients_are_ordered_by_id_whatever_the_input_order() {
+        let report = process_file("transactions_unordered_clients.csv").unwrap();
+        let ids: Vec<u16> = report.clients.iter().map(|client| client.client_id).collect();
+        assert_eq!(ids, [0, 7, 42, 300, 65535]);
+    }
+
+    #[test]
+    fn amounts_in_scientific_notation_or_with_separators_are_parsed() {
+        let options = ProcessOptions::new();
+        let thousand = Decimal::new(1000, 0);
+        for value in ["1e3", "1_000", "1,000.00", "1000"] {
+            assert_eq!(parse_amount(1, value, &options).unwrap(), thousand, "{}", value);
+        }
+        assert_eq!(parse_amount(1, "1,234,567.5", &options).unwrap(), Decimal::new(12_345_675, 1));
+    }
+
+    #[test]
+    fn invalid_amounts_are_parse_errors() {
+        let options = ProcessOptions::new();
+        for value in ["ten", "1,5", "1,0000", "12,34.5", "1.000,5"] {
+            let err = parse_amount(7, value, &options).unwrap_err();
+            assert!(matches!(err, ProcessError::ParseAmount { transaction_id: 7, value: ref parsed } if parsed == value), "{}: {}", value, err);
+        }
+        assert_eq!(strip_thousands_separators("1,5", ',', '.'), None);
+        assert_eq!(strip_thousands_separators("-1,500.25", ',', '.').as_deref(), Some("-1500.25"));
+    }
+
+    #[test]
+    fn amounts_above_the_maximum_are_rejected() {
+        let max_amount = Decimal::new(1_000_000_000, 0);
+        let report = process_file_with("transactions_max_amount.csv", &ProcessOptions::new().max_amount(max_amount)).unwrap();
+        assert_eq!(report.clients[0].available, Decimal::new(1_000_000_400, 0));
+        let rejected: Vec<(u32, &RejectReason)> = report.rejected.iter().map(|rejected| (rejected.transaction_id, &rejected.reason)).collect();
+        let exceeds = RejectReason::ExceedsMaxAmount { max_amount };
+        assert_eq!(rejected, [(1, &exceeds), (4, &exceeds), (5, &exceeds)]);
+    }
+
+    #[test]
+    fn conservation_check_passes_on_processed_accounts() {
+        for filename in ["transactions.csv", "transactions_partial_disputes.csv", "transactions_held_withdrawals.csv"] {
+            let report = process_file(filename).unwrap();
+            assert_eq!(check_conservation(&report), Ok(()), "{}", filename);
+        }
+    }
+
+    #[test]
+    fn conservation_check_fails_on_corrupted_accounts() {
+        let report = process_after_state("transactions_state_corrupted.json", "transactions_empty.csv", &ProcessOptions::new()).unwrap();
+        let err = check_conservation(&report).unwrap_err();
+        assert!(err.contains("client 1"), "{}", err);
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped_before_the_first_row() {
+        let report = process("\u{feff}type,client,tx,amount\ndeposit,1,1,1.0\n", &ProcessOptions::new()).unwrap();
+        assert_eq!(report.clients[0].available, Decimal::ONE);
+        assert!(report.rejected.is_empty());
+
+        let report = process("\u{feff}deposit,1,1,1.0\ndeposit,1,2,2.0\n", &ProcessOptions::new().has_headers(false)).unwrap();
+        assert_eq!(report.clients[0].available, Decimal::new(3, 0));
+        assert_eq!(report.counts.rows, 2);
+    }
+
+    #[test]
+    fn locked_accounts_reject_deposits_and_withdrawals() {
+        let options = ProcessOptions::new();
+        for status in [AccountStatus::Frozen, AccountStatus::ChargedBack] {
+            let mut client = Client::new(1);
+            apply_transaction(&mut client, "deposit", 1, "10.0", &options).unwrap();
+            client.status = status;
+            for (transaction_type, transaction_id) in [("deposit", 2), ("withdrawal", 3)] {
+                let reason = apply_transaction(&mut client, transaction_type, transaction_id, "1.0", &options).unwrap();
+                assert_eq!(reason, Some(RejectReason::AccountLocked { status }), "{} on {}", transaction_type, status);
+            }
+            assert_eq!(client.available, Decimal::new(10, 0));
+        }
+    }
+
+    #[test]
+    fn account_status_is_written_by_name() {
+        let report = process_after_state("transactions_state_frozen.json", "transactions_empty.csv", &ProcessOptions::new()).unwrap();
+        assert_eq!(report.clients[0].status, AccountStatus::Frozen);
+        let mut written = Vec::new();
+        write_output(&report.clients, &mut written, &OutputOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "client, available, held, total, locked, status\n1,10.0000,25.0000,35.0000,true,frozen\n");
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn unreachable_url_is_an_http_error() {
+        let mut clients: HashMap<u16, Client> = HashMap::new();
+        let mut rejected: Vec<RejectedTransaction> = Vec::new();
+        let mut counts = TransactionCounts::default();
+        let err = read_csv_url("http://127.0.0.1:9/", &mut clients, &mut rejected, &mut counts, &ProcessOptions::new()).unwrap_err();
+        assert!(matches!(err, ProcessError::Http(_)), "{}", err);
+        assert!(clients.is_empty());
+    }
+
+    #[test]
+    fn deposit_to_a_charged_back_account_is_rejected_as_locked() {
+        let report = process_file("transactions_locked_deposit.csv").unwrap();
+        assert_eq!(report.clients[0].available, Decimal::ZERO);
+        let rejected: Vec<(u64, &RejectReason)> = report.rejected.iter().map(|rejected| (rejected.line, &rejected.reason)).collect();
+        let locked = RejectReason::AccountLocked { status: AccountStatus::ChargedBack };
+        assert_eq!(rejected, [(5, &locked), (6, &locked)]);
+    }
+
+    #[test]
+    fn amounts_are_rounded_by_the_chosen_mode() {
+        let amounts: Vec<Decimal> = ["1.00005", "1.00015", "1.00019", "2.00025"].iter().map(|amount| amount.parse().unwrap()).collect();
+        let modes = [
+            (Rounding::HalfEven, ["1.0000", "1.0002", "1.0002", "2.0002"]),
+            (Rounding::HalfUp, ["1.0001", "1.0002", "1.0002", "2.0003"]),
+            (Rounding::Truncate, ["1.0000", "1.0001", "1.0001", "2.0002"]),
+        ];
+        for (rounding, expected) in modes {
+            let options = OutputOptions { rounding, ..OutputOptions::default() };
+            let formatted: Vec<String> = amounts.iter().map(|amount| format_amount(*amount, &options)).collect();
+            assert_eq!(formatted, expected, "{:?}", rounding);
+        }
+    }
+
+    #[test]
+    fn recomputed_balances_replace_corrupted_ones() {
+        let report = process_file("transactions_partial_disputes.csv").unwrap();
+        for client in &report.clients {
+            let mut corrupted = client.clone();
+            corrupted.available += Decimal::new(7, 0);
+            corrupted.held = Decimal::new(-3, 0);
+            recompute_balances(&mut corrupted);
+            assert_eq!((corrupted.available, corrupted.held, corrupted.total()), (client.available, client.held, client.total()), "client {}", client.client_id);
+        }
+    }
+
+    #[test]
+    fn written_accounts_read_back_as_the_clients() {
+        let report = process_file("transactions.csv").unwrap();
+        let mut written = Vec::new();
+        write_output(&report.clients, &mut written, &OutputOptions::default()).unwrap();
+        let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(written.as_slice());
+        assert_eq!(rdr.headers().unwrap(), vec!["client", "available", "held", "total", "locked", "status"]);
+        let mut read = Vec::new();
+        for record in rdr.records() {
+            let record = record.unwrap();
+            let parse = |index: usize| record[index].parse::<Decimal>().unwrap();
+            read.push((record[0].parse::<u16>().unwrap(), parse(1), parse(2), parse(3), record[4].parse::<bool>().unwrap(), record[5].to_string()));
+        }
+        let expected: Vec<_> = report.clients.iter().map(|client| (client.client_id, client.available, client.held, client.total(), client.is_locked(), client.status.to_string())).collect();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn currency_symbol_is_only_removed_when_set() {
+        let with_symbol = ProcessOptions::new().currency_symbol("$");
+        assert_eq!(parse_amount(1, "$100.00", &with_symbol).unwrap(), Decimal::new(10000, 2));
+        assert!(matches!(parse_amount(1, "$100.00", &ProcessOptions::new()), Err(ProcessError::ParseAmount { transaction_id: 1, ref value }) if value == "$100.00"));
+        let report = process_file_with("transactions_currency_symbol.csv", &with_symbol).unwrap();
+        let balances = balances_map(&report.clients);
+        assert_eq!(balances[&1], (Decimal::new(130025, 2), Decimal::ZERO, false));
+        assert_eq!(balances[&2], (Decimal::new(10, 0), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn out_of_order_rows_only_balance_when_processed_chronologically() {
+        let unordered = process_file("transactions_timestamps.csv").unwrap();
+        assert_eq!(balances_map(&unordered.clients)[&1], (Decimal::new(10, 0), Decimal::ZERO, false));
+        assert!(!unordered.rejected.is_empty());
+        let chronological = process_file_with("transactions_timestamps.csv", &ProcessOptions::new().chronological(true)).unwrap();
+        let balances = balances_map(&chronological.clients);
+        assert_eq!(balances[&1], (Decimal::new(6, 0), Decimal::ZERO, false));
+        assert_eq!(balances[&2], (Decimal::ZERO, Decimal::new(5, 0), false));
+        assert!(chronological.rejected.is_empty());
+    }
+
+    #[test]
+    fn leading_withdrawal_under_each_new_client_policy() {
+        let deposit_only = process_file("transactions_leading_withdrawal.csv").unwrap();
+        let rejected: Vec<_> = deposit_only.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(2, RejectReason::UnknownClient), (5, RejectReason::UnknownClient), (7, RejectReason::UnknownClient)]);
+        let balances = balances_map(&deposit_only.clients);
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[&1], (Decimal::new(6, 0), Decimal::ZERO, false));
+        let any = process_file_with("transactions_leading_withdrawal.csv", &ProcessOptions::new().new_client_policy(NewClientPolicy::AnyTransaction)).unwrap();
+        let rejected: Vec<_> = any.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![
+            (2, RejectReason::InsufficientFunds { requested: Decimal::new(50, 1), available: Decimal::ZERO }),
+            (5, RejectReason::OtherClientTransaction { owner: 1 }),
+            (7, RejectReason::InsufficientFunds { requested: Decimal::new(10, 1), available: Decimal::ZERO })
+        ]);
+        let balances = balances_map(&any.clients);
+        assert_eq!(balances[&1], (Decimal::new(6, 0), Decimal::ZERO, false));
+        assert_eq!(balances[&3], (Decimal::ZERO, Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn non_finite_amounts_are_parse_errors_that_leave_the_balance_alone() {
+        let options = ProcessOptions::new();
+        let mut clients = HashMap::new();
+        let mut rejected = Vec::new();
+        process_line("deposit,1,1,10.0", 1, &mut clients, &mut rejected, &options).unwrap();
+        for (index, value) in ["NaN", "inf", "-inf", "Infinity"].into_iter().enumerate() {
+            let line = index as u64 + 2;
+            let err = process_line(&format!("deposit,1,{},{}", line, value), line, &mut clients, &mut rejected, &options).unwrap_err();
+            assert!(matches!(err, ProcessError::Line { line: l, ref error } if l == line && matches!(error.as_ref(), ProcessError::ParseAmount { value: parsed, .. } if parsed == value)), "{}: {}", value, err);
+            assert_eq!(balances_map(&clients.values().cloned().collect::<Vec<_>>())[&1], (Decimal::new(100, 1), Decimal::ZERO, false));
+        }
+        assert!(rejected.is_empty());
+        let err = process_file("transactions_non_finite.csv").unwrap_err();
+        assert!(matches!(err, ProcessError::Line { line: 3, ref error } if matches!(error.as_ref(), ProcessError::ParseAmount { transaction_id: 2, .. })), "{}", err);
+    }
+
+    #[test]
+    fn frozen_account_rejects_deposits_until_unfrozen() {
+        let report = process_file_with("transactions_freeze.csv", &ProcessOptions::new().admin_transactions(true)).unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![
+            (4, RejectReason::AccountLocked { status: AccountStatus::Frozen }),
+            (5, RejectReason::AccountLocked { status: AccountStatus::Frozen })
+        ]);
+        assert_eq!(report.clients[0].status, AccountStatus::Active);
+        assert_eq!(balances_map(&report.clients)[&1], (Decimal::new(150, 1), Decimal::ZERO, false));
+        let report = process_file("transactions_freeze.csv").unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(3, RejectReason::UnknownTransactionType), (6, RejectReason::UnknownTransactionType)]);
+        assert_eq!(balances_map(&report.clients)[&1], (Decimal::new(190, 1), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn disputes_of_reused_ids_act_on_the_original_transactions() {
+        //The reused ids are discarded, so the dispute of tx 2 holds the 3.0 withdrawal rather than the 20.0 deposit
+        let events: Vec<_> = process_events(File::open("transactions_reused_ids.csv").unwrap(), &ProcessOptions::new()).unwrap()
+            .map(|event| event.map(|event| (event.transaction_type, event.available, event.held)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(events, vec![
+            ("deposit".to_string(), Decimal::new(100, 1), Decimal::ZERO),
+            ("withdrawal".to_string(), Decimal::new(70, 1), Decimal::ZERO),
+            ("dispute".to_string(), Decimal::new(70, 1), Decimal::new(30, 1)),
+            ("chargeback".to_string(), Decimal::new(100, 1), Decimal::ZERO)
+        ]);
+    }
+
+    #[test]
+    fn resolved_transaction_is_only_disputed_again_when_allowed() {
+        let report = process_file("transactions_redispute.csv").unwrap();
+        assert!(report.rejected.is_empty());
+        assert_eq!(balances_map(&report.clients)[&1], (Decimal::ZERO, Decimal::ZERO, true));
+        let report = process_file_with("transactions_redispute.csv", &ProcessOptions::new().allow_redispute(false)).unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(5, RejectReason::AlreadyResolved), (6, RejectReason::NotDisputed)]);
+        assert_eq!(balances_map(&report.clients)[&1], (Decimal::new(100, 1), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn exact_decimals_withdraw_down_to_zero() {
+        //0.1 + 0.2 - 0.3 is not zero in floating point, but is exactly zero as a decimal
+        let report = process_file("transactions_exact_withdrawal.csv").unwrap();
+        let balances = balances_map(&report.clients);
+        for client_id in [1, 2, 3] {
+            assert_eq!(balances[&client_id], (Decimal::ZERO, Decimal::ZERO, false), "client {}", client_id);
+        }
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(7, RejectReason::InsufficientFunds { requested: Decimal::new(1, 4), available: Decimal::ZERO })]);
+    }
+
+    #[test]
+    fn quoted_fields_are_read_as_unquoted() {
+        let report = process_file("transactions_quoted.csv").unwrap();
+        assert!(report.rejected.is_empty());
+        let balances = balances_map(&report.clients);
+        assert_eq!(balances[&1], (Decimal::new(-255, 1), Decimal::new(100, 0), false));
+        assert_eq!(balances[&2], (Decimal::new(125050, 2), Decimal::ZERO, false));
+        assert_eq!(report.clients.iter().find(|client| client.client_id == 2).unwrap().current_transactions[0].amount_raw, "1,250.50");
+    }
+
+    #[test]
+    fn mapped_type_names_are_processed_as_their_types() {
+        let type_names = HashMap::from([("credit".to_string(), TransactionType::Deposit)]);
+        let report = process_file_with("transactions_renamed_types.csv", &ProcessOptions::new().type_names(type_names)).unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(3, RejectReason::UnknownTransactionType), (5, RejectReason::UnknownTransactionType), (6, RejectReason::NotDisputed)]);
+        assert_eq!(balances_map(&report.clients)[&1], (Decimal::new(110, 1), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn jsonl_rows_give_the_same_accounts_as_csv() {
+        let mut clients: HashMap<u16, Client> = HashMap::new();
+        let mut rejected: Vec<RejectedTransaction> = Vec::new();
+        let mut counts = TransactionCounts::default();
+        read_jsonl_file("transactions.jsonl", &mut clients, &mut rejected, &mut counts, &ProcessOptions::new()).unwrap();
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\ndeposit,1,3,2.0\nwithdrawal,1,4,1.5\nwithdrawal,2,5,3.0\ndispute,1,1,\nresolve,1,1,\ndispute,2,2,\nchargeback,2,2,\n";
+        let report = process(csv, &ProcessOptions::new()).unwrap();
+        let from_jsonl = balances_map(&clients.into_values().collect::<Vec<_>>());
+        assert_eq!(from_jsonl, balances_map(&report.clients));
+        assert_eq!(from_jsonl[&1], (Decimal::new(15, 1), Decimal::ZERO, false));
+        let reasons = |rejected: &[RejectedTransaction]| rejected.iter().map(|r| r.reason.clone()).collect::<Vec<_>>();
+        assert_eq!(reasons(&rejected), reasons(&report.rejected));
+    }
+
+    #[test]
+    fn disputed_transactions_lists_only_the_open_disputes() {
+        let csv = "type,client,tx,amount\ndeposit,2,1,5.0\ndeposit,1,2,10.0\ndeposit,1,3,4.0\ndispute,2,1,\ndispute,1,3,\ndispute,1,2,2.5\ndeposit,1,4,1.0\ndispute,1,4,\nresolve,1,4,\n";
+        let report = process(csv, &ProcessOptions::new()).unwrap();
+        assert_eq!(disputed_transactions(&report.clients), vec![(1, 2, Decimal::new(25, 1)), (1, 3, Decimal::new(40, 1)), (2, 1, Decimal::new(50, 1))]);
+    }
+
+    #[test]
+    fn semicolon_delimited_file_with_decimal_commas() {
+        let report = process_file_with("transactions_decimal_comma.csv", &ProcessOptions::new().delimiter(b';').decimal_comma(true)).unwrap();
+        assert!(report.rejected.is_empty());
+        let balances = balances_map(&report.clients);
+        assert_eq!(balances[&1], (Decimal::new(1335, 0), Decimal::ZERO, false));
+        assert_eq!(balances[&2], (Decimal::new(95, 1), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn held_withdrawals_are_released_or_cancelled() {
+        let events: Vec<_> = process_events(File::open("transactions_held_withdrawals.csv").unwrap(), &ProcessOptions::new()).unwrap()
+            .map(|event| event.map(|event| (event.client_id, event.transaction_type, event.available, event.held)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        //The dispute of the pending hold on line 4 is discarded, so it yields no event and is not rejected
+        assert_eq!(events, vec![
+            (1, "deposit".to_string(), Decimal::new(100, 1), Decimal::ZERO),
+            (1, "hold".to_string(), Decimal::new(60, 1), Decimal::new(40, 1)),
+            (1, "release".to_string(), Decimal::new(60, 1), Decimal::ZERO),
+            (2, "deposit".to_string(), Decimal::new(100, 1), Decimal::ZERO),
+            (2, "hold".to_string(), Decimal::new(40, 1), Decimal::new(60, 1)),
+            (2, "cancel".to_string(), Decimal::new(100, 1), Decimal::ZERO),
+            (2, "hold".to_string(), Decimal::new(70, 1), Decimal::new(30, 1))
+        ]);
+        let report = process_file("transactions_held_withdrawals.csv").unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![
+            (6, RejectReason::NotPending),
+            (11, RejectReason::InsufficientFunds { requested: Decimal::new(110, 1), available: Decimal::new(100, 1) })
+        ]);
+    }
+
+    #[test]
+    fn malformed_row_is_reported_with_its_line() {
+        let err = process_file("transactions_malformed_row.csv").unwrap_err();
+        assert!(matches!(err, ProcessError::Line { line: 5, ref error } if matches!(error.as_ref(), ProcessError::ParseAmount { transaction_id: 4, value } if value == "1.O")), "{}", err);
+    }
+
+    #[test]
+    fn next_day_disputes_act_on_the_saved_deposits() {
+        //Tx 2 was left in dispute by the saved state and is resolved, while tx 1 is disputed and charged back
+        let report = process_after_state("transactions_state.json", "transactions_state_next_day.csv", &ProcessOptions::new()).unwrap();
+        assert!(report.rejected.is_empty());
+        let client = &report.clients[0];
+        assert_eq!((client.available, client.held, client.status), (Decimal::new(300, 1), Decimal::ZERO, AccountStatus::ChargedBack));
+        let saved: Vec<_> = client.current_transactions.iter().filter(|trans| trans.transaction_id <= 2).map(|trans| (trans.transaction_id, trans.in_dispute, trans.charged_back)).collect();
+        assert_eq!(saved, vec![(1, false, true), (2, false, false)]);
+    }
+
+    #[test]
+    fn disabled_chargebacks_leave_the_account_active() {
+        let report = process_file_with("transactions_locked_deposit.csv", &ProcessOptions::new().disabled_types(vec![TransactionType::Chargeback])).unwrap();
+        assert!(report.rejected.is_empty());
+        assert_eq!(report.clients[0].status, AccountStatus::Active);
+        assert_eq!(balances_map(&report.clients)[&1], (Decimal::new(40, 1), Decimal::new(100, 1), false));
+        assert_eq!((report.counts.chargebacks, report.counts.skipped), (1, 1));
+    }
+
+    #[test]
+    fn first_line_is_a_row_without_headers() {
+        //The first line is the 1.0 deposit for client 1 rather than a header
+        let report = process_file_with("transactions_no_header.csv", &ProcessOptions::new().has_headers(false)).unwrap();
+        let balances = balances_map(&report.clients);
+        assert_eq!(balances[&1], (Decimal::new(15, 1), Decimal::ZERO, false));
+        assert_eq!(report.counts.deposits, 3);
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(5, RejectReason::InsufficientFunds { requested: Decimal::new(30, 1), available: Decimal::new(20, 1) })]);
+    }
+
+    #[test]
+    fn balances_map_holds_each_account_by_client() {
+        let report = process_file("transactions_reused_ids.csv").unwrap();
+        assert_eq!(balances_map(&report.clients), HashMap::from([(1, (Decimal::new(100, 1), Decimal::ZERO, true))]));
+        let report = process_file("transactions_timestamps.csv").unwrap();
+        assert_eq!(balances_map(&report.clients), HashMap::from([
+            (1, (Decimal::new(100, 1), Decimal::ZERO, false)),
+            (2, (Decimal::ZERO, Decimal::new(50, 1), false))
+        ]));
+    }
+
+    #[test]
+    fn amount_raw_keeps_the_amount_as_written() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,\"1,000.00\"\ndeposit,1,2,$5\n";
+        let report = process(csv, &ProcessOptions::new().currency_symbol("$")).unwrap();
+        let amounts: Vec<_> = report.clients[0].current_transactions.iter().map(|trans| (trans.amount, trans.amount_raw.as_str())).collect();
+        assert_eq!(amounts, vec![(Decimal::new(100000, 2), "1,000.00"), (Decimal::new(5, 0), "$5")]);
+    }
+
+    #[test]
+    fn transactions_beyond_the_limit_are_rejected() {
+        let report = process_file_with("transactions_rate_limit.csv", &ProcessOptions::new().max_transactions_per_client(3)).unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(6, RejectReason::TransactionLimit { limit: 3 }), (7, RejectReason::TransactionLimit { limit: 3 })]);
+        let balances = balances_map(&report.clients);
+        assert_eq!(balances[&1], (Decimal::new(110, 1), Decimal::ZERO, false));
+        assert_eq!(balances[&2], (Decimal::new(60, 1), Decimal::ZERO, false));
+    }
+
+    #[test]
+    fn replayed_transactions_are_applied_once_when_idempotent() {
+        let replay = |options: &ProcessOptions| {
+            let mut clients: HashMap<u16, Client> = HashMap::new();
+            let mut rejected: Vec<RejectedTransaction> = Vec::new();
+            let mut counts = TransactionCounts::default();
+            for _ in 0..2 {
+                read_csv("transactions_replayed.csv".to_string(), &mut clients, &mut rejected, &mut counts, options).unwrap();
+            }
+            balances_map(&clients.into_values().collect::<Vec<_>>())
+        };
+        let idempotent = replay(&ProcessOptions::new().idempotent(true));
+        assert_eq!(idempotent[&1], (Decimal::new(60, 1), Decimal::new(40, 1), false));
+        assert_eq!(idempotent[&2], (Decimal::new(40, 1), Decimal::ZERO, false));
+        let replayed = replay(&ProcessOptions::new());
+        assert_eq!(replayed[&1], (Decimal::new(20, 1), Decimal::new(80, 1), false));
+        //Transactions are remembered for each client, so the same dispute from another client is not taken as a replay
+        let csv = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\ndispute,1,1,4.0\ndispute,2,1,4.0\n";
+        let report = process(csv, &ProcessOptions::new().idempotent(true)).unwrap();
+        let rejected: Vec<_> = report.rejected.iter().map(|r| (r.line, r.reason.clone())).collect();
+        assert_eq!(rejected, vec![(5, RejectReason::OtherClientTransaction { owner: 1 })]);
+    }
+}
@@ -2,27 +2,542 @@
 //Developer: William Chipman
 //Last Updated: 14 Feb 2022
 
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufWriter, IsTerminal};
 use std::process;
+use std::slice;
+use std::time::Instant;
 
-mod lib;
-use lib::lib::Client;
+use rust_decimal::Decimal;
+use rustcodingtest::{check_conservation, load_state, process_line, read_csv, read_csv_reader, read_jsonl, read_jsonl_file, recompute_balances, save_state, sorted_clients, validate_file_with, validate_reader, write_disputes, write_output};
+#[cfg(feature = "url")]
+use rustcodingtest::read_csv_url;
+#[cfg(feature = "progress")]
+use rustcodingtest::{read_csv_reader_with_progress, read_csv_with_progress};
+use rustcodingtest::{Client, NewClientPolicy, OutputColumn, OutputFormat, OutputOptions, ProcessError, ProcessOptions, ProcessReport, RejectedTransaction, Rounding, TransactionCounts, TransactionType};
+
+//Exit codes, so scripts can tell why a run failed. FAILURE covers anything else, such as a failed --check
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_PARSE: i32 = 4;
+const EXIT_REJECTED: i32 = 5;
+
+fn exit_code(err: &ProcessError) -> i32 {
+    //A CSV error can also be a failure to read the underlying file
+    match err {
+        ProcessError::Io(_) => EXIT_IO,
+        ProcessError::Csv(err) if matches!(err.kind(), csv::ErrorKind::Io(_)) => EXIT_IO,
+        #[cfg(feature = "url")]
+        ProcessError::Http(_) => EXIT_IO,
+        ProcessError::Json { error, .. } if error.is_io() => EXIT_IO,
+        ProcessError::Line { error, .. } => exit_code(error),
+        ProcessError::ThreadPool(_) => EXIT_FAILURE,
+        _ => EXIT_PARSE,
+    }
+}
+
+fn parse_transaction_type(name: &str) -> Result<TransactionType, String> {
+    //Reads a transaction type given on the command line by its default name
+    match name {
+        "deposit" => Ok(TransactionType::Deposit),
+        "withdrawal" => Ok(TransactionType::Withdrawal),
+        "dispute" => Ok(TransactionType::Dispute),
+        "resolve" => Ok(TransactionType::Resolve),
+        "chargeback" => Ok(TransactionType::Chargeback),
+        "hold" => Ok(TransactionType::Hold),
+        "release" => Ok(TransactionType::Release),
+        "cancel" => Ok(TransactionType::Cancel),
+        "freeze" => Ok(TransactionType::Freeze),
+        "unfreeze" => Ok(TransactionType::Unfreeze),
+        other => Err(format!("unknown transaction type '{}'", other)),
+    }
+}
+
+//Largest number of decimal places that can be requested with --precision
+const MAX_PRECISION: usize = 10;
+
+//Options read from the command line
+struct Args {
+    filenames: Vec<String>,
+    threads: usize,
+    delimiter: u8,
+    currency_symbol: Option<String>,
+    decimal_comma: bool,
+    no_header: bool,
+    idempotent: bool,
+    overdraft_limit: Decimal,
+    retain: Option<usize>,
+    max_transactions_per_client: Option<usize>,
+    validate: bool,
+    quiet: bool,
+    repl: bool,
+    stats: bool,
+    check: bool,
+    disputes: bool,
+    recompute: bool,
+    chronological: bool,
+    new_client_policy: NewClientPolicy,
+    admin_transactions: bool,
+    no_redispute: bool,
+    clients: Option<HashSet<u16>>,
+    jsonl: bool,
+    type_names: HashMap<String, TransactionType>,
+    disabled_types: Vec<TransactionType>,
+    #[cfg(feature = "progress")]
+    progress: bool,
+    load_state: Option<String>,
+    save_state: Option<String>,
+    output_path: Option<String>,
+    output: OutputOptions
+}
+
+fn csv_files_in(dir: &str) -> Result<Vec<String>, String> {
+    //Lists the CSV files in a directory, including gzip compressed ones, sorted by file name so
+    //files named by date are processed in date order. Anything else in the directory is skipped
+    let entries = fs::read_dir(dir).map_err(|err| format!("can not read directory {}: {}", dir, err))?;
+    let mut filenames = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|err| format!("can not read directory {}: {}", dir, err))?.path();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if path.is_file() && (name.ends_with(".csv") || name.ends_with(".csv.gz")) {
+            filenames.push(path.to_string_lossy().into_owned());
+        }
+    }
+    //With no files the transactions would be read from stdin instead, so an empty directory is an error
+    if filenames.is_empty() {
+        return Err(format!("no CSV files found in directory {}", dir));
+    }
+    filenames.sort();
+    Ok(filenames)
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    //Flags may appear in any order. Any argument that is not a flag is taken as a CSV file name
+    //The files in a directory given with --dir are processed at the point it appears among the file names
+    let mut parsed = Args { filenames: Vec::new(), threads: 1, delimiter: b',', currency_symbol: None, decimal_comma: false, no_header: false, idempotent: false, overdraft_limit: Decimal::ZERO, retain: None, max_transactions_per_client: None, validate: false, quiet: false, repl: false, stats: false, check: false, disputes: false, recompute: false, chronological: false, new_client_policy: NewClientPolicy::DepositOnly, admin_transactions: false, no_redispute: false, clients: None, jsonl: false, type_names: HashMap::new(), disabled_types: Vec::new(), #[cfg(feature = "progress")] progress: false, load_state: None, save_state: None, output_path: None, output: OutputOptions::default() };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                parsed.output.format = match args.next().map(String::as_str) {
+                    Some("csv") => OutputFormat::Csv,
+                    Some("json") => OutputFormat::Json,
+                    #[cfg(feature = "bincode")]
+                    Some("bincode") => OutputFormat::Bincode,
+                    #[cfg(not(feature = "bincode"))]
+                    Some("bincode") => return Err("--format bincode needs a build with the bincode feature".to_string()),
+                    Some(other) => return Err(format!("unknown output format '{}'", other)),
+                    None => return Err("--format requires a value".to_string()),
+                };
+            }
+            "--input-format" => {
+                parsed.jsonl = match args.next().map(String::as_str) {
+                    Some("csv") => false,
+                    Some("jsonl") => true,
+                    Some(other) => return Err(format!("unknown input format '{}'", other)),
+                    None => return Err("--input-format requires a value".to_string()),
+                };
+            }
+            "--precision" => {
+                let value = args.next().ok_or("--precision requires a value")?;
+                parsed.output.precision = match value.parse() {
+                    Ok(precision) if precision <= MAX_PRECISION => precision,
+                    _ => return Err(format!("precision must be a number from 0 to {}, got '{}'", MAX_PRECISION, value)),
+                };
+            }
+            "--columns" => {
+                let value = args.next().ok_or("--columns requires a list of columns such as client,total,locked")?;
+                let mut columns = Vec::new();
+                for name in value.split(',') {
+                    let name = name.trim();
+                    let column = OutputColumn::ALL.into_iter().find(|column| column.name() == name).ok_or_else(|| {
+                        let known: Vec<&str> = OutputColumn::ALL.iter().map(|column| column.name()).collect();
+                        format!("unknown column '{}', expected one of {}", name, known.join(", "))
+                    })?;
+                    columns.push(column);
+                }
+                parsed.output.columns = columns;
+            }
+            "--rounding" => {
+                parsed.output.rounding = match args.next().map(String::as_str) {
+                    Some("half-even") => Rounding::HalfEven,
+                    Some("half-up") => Rounding::HalfUp,
+                    Some("truncate") => Rounding::Truncate,
+                    Some(other) => return Err(format!("unknown rounding mode '{}'", other)),
+                    None => return Err("--rounding requires a value".to_string()),
+                };
+            }
+            "--new-clients" => {
+                parsed.new_client_policy = match args.next().map(String::as_str) {
+                    Some("deposit-only") => NewClientPolicy::DepositOnly,
+                    Some("any") => NewClientPolicy::AnyTransaction,
+                    Some(other) => return Err(format!("unknown new client policy '{}'", other)),
+                    None => return Err("--new-clients requires a value".to_string()),
+                };
+            }
+            "--clients" => {
+                let value = args.next().ok_or("--clients requires a list of client ids")?;
+                let mut clients = HashSet::new();
+                for id in value.split(',') {
+                    match id.trim().parse() {
+                        Ok(client_id) => clients.insert(client_id),
+                        Err(_) => return Err(format!("client ids must be numbers from 0 to 65535, got '{}'", id)),
+                    };
+                }
+                parsed.clients = Some(clients);
+            }
+            #[cfg(feature = "progress")]
+            "--progress" => parsed.progress = true,
+            #[cfg(not(feature = "progress"))]
+            "--progress" => return Err("--progress needs a build with the progress feature".to_string()),
+            "--type-name" => {
+                let value = args.next().ok_or("--type-name requires a value such as credit=deposit")?;
+                let (name, transaction_type) = value.split_once('=').ok_or_else(|| format!("type name must be given as name=type, got '{}'", value))?;
+                parsed.type_names.insert(name.to_string(), parse_transaction_type(transaction_type)?);
+            }
+            "--disable" => {
+                let value = args.next().ok_or("--disable requires a list of transaction types such as chargeback,dispute")?;
+                for transaction_type in value.split(',') {
+                    parsed.disabled_types.push(parse_transaction_type(transaction_type.trim())?);
+                }
+            }
+            "--verbose" => parsed.output.verbose = true,
+            "--summary" => parsed.output.summary = true,
+            "--validate" => parsed.validate = true,
+            "--quiet" => parsed.quiet = true,
+            "--repl" => parsed.repl = true,
+            "--stats" => parsed.stats = true,
+            "--check" => parsed.check = true,
+            "--disputes" => parsed.disputes = true,
+            "--recompute" => parsed.recompute = true,
+            "--chronological" => parsed.chronological = true,
+            "--admin-transactions" => parsed.admin_transactions = true,
+            "--no-redispute" => parsed.no_redispute = true,
+            "--dir" => parsed.filenames.extend(csv_files_in(args.next().ok_or("--dir requires a path")?)?),
+            "--output" => parsed.output_path = Some(args.next().ok_or("--output requires a path")?.clone()),
+            "--load-state" => parsed.load_state = Some(args.next().ok_or("--load-state requires a path")?.clone()),
+            "--save-state" => parsed.save_state = Some(args.next().ok_or("--save-state requires a path")?.clone()),
+            "--overdraft-limit" => {
+                let value = args.next().ok_or("--overdraft-limit requires a value")?;
+                parsed.overdraft_limit = match value.parse::<Decimal>() {
+                    Ok(limit) if !limit.is_sign_negative() => limit,
+                    _ => return Err(format!("overdraft limit must be a number of zero or more, got '{}'", value)),
+                };
+            }
+            "--retain" => {
+                let value = args.next().ok_or("--retain requires a value")?;
+                parsed.retain = match value.parse() {
+                    Ok(retain) if retain > 0 => Some(retain),
+                    _ => return Err(format!("transactions to retain must be a number of at least 1, got '{}'", value)),
+                };
+            }
+            "--max-transactions-per-client" => {
+                let value = args.next().ok_or("--max-transactions-per-client requires a value")?;
+                parsed.max_transactions_per_client = match value.parse() {
+                    Ok(limit) => Some(limit),
+                    Err(_) => return Err(format!("transaction limit must be a number, got '{}'", value)),
+                };
+            }
+            "--threads" => {
+                let value = args.next().ok_or("--threads requires a value")?;
+                parsed.threads = match value.parse() {
+                    Ok(threads) if threads > 0 => threads,
+                    _ => return Err(format!("threads must be a number greater than zero, got '{}'", value)),
+                };
+            }
+            "--delimiter" => {
+                let value = args.next().ok_or("--delimiter requires a value")?;
+                parsed.delimiter = match value.as_bytes() {
+                    [delimiter] => *delimiter,
+                    _ => return Err(format!("delimiter must be a single ASCII character, got '{}'", value)),
+                };
+            }
+            "--decimal-comma" => parsed.decimal_comma = true,
+            "--no-header" => parsed.no_header = true,
+            "--idempotent" => parsed.idempotent = true,
+            "--currency-symbol" => parsed.currency_symbol = Some(args.next().ok_or("--currency-symbol requires a value")?.clone()),
+            _ => parsed.filenames.push(arg.clone()),
+        }
+    }
+    //With commas between the fields a decimal comma would split the amount in two
+    if parsed.decimal_comma && parsed.delimiter == b',' {
+        return Err("--decimal-comma needs a --delimiter other than a comma".to_string());
+    }
+    Ok(parsed)
+}
+
+fn validate(args: &Args, options: &ProcessOptions) -> i32 {
+    //Checks each input without processing any transactions. Prints a summary line per input, then the
+    //malformed rows of every input after a blank line. Returns EXIT_PARSE if any row is malformed
+    //With --quiet nothing but errors reading an input is printed, leaving just the exit code
+    let inputs: Vec<&str> = if args.filenames.is_empty() { vec!["-"] } else { args.filenames.iter().map(String::as_str).collect() };
+    let mut malformed = Vec::new();
+    if !args.quiet {
+        println!("file, rows, deposits, withdrawals, disputes, resolves, chargebacks, malformed");
+    }
+    for input in inputs {
+        let result = if input == "-" {
+            validate_reader(io::stdin(), options)
+        } else {
+            validate_file_with(input, options)
+        };
+        let report = match result {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("Error validating {}: {}", input, err);
+                return exit_code(&err);
+            }
+        };
+        if !args.quiet {
+            println!("{},{},{},{},{},{},{},{}", input, report.rows, report.deposits, report.withdrawals, report.disputes, report.resolves, report.chargebacks, report.malformed.len());
+        }
+        malformed.extend(report.malformed.into_iter().map(|row| (input, row)));
+    }
+    if malformed.is_empty() {
+        return 0;
+    }
+    if args.quiet {
+        return EXIT_PARSE;
+    }
+    println!();
+    println!("file, line, error");
+    for (input, row) in malformed {
+        println!("{},{},\"{}\"", input, row.line, row.error.to_string().replace('"', "\"\""));
+    }
+    EXIT_PARSE
+}
+
+fn initial_clients(args: &Args) -> HashMap<u16, Client> {
+    //Starts from the client state saved by an earlier run when --load-state is given, otherwise from no clients
+    //With --recompute the loaded balances are replaced by the ones their stored transactions add up to
+    let path = match &args.load_state {
+        Some(path) => path,
+        None => return HashMap::new(),
+    };
+    match load_state(path) {
+        Ok(clients) => clients.into_iter().map(|mut client| {
+            if args.recompute {
+                recompute_balances(&mut client);
+            }
+            (client.client_id, client)
+        }).collect(),
+        Err(err) => {
+            eprintln!("Error loading state from {}: {}", path, err);
+            process::exit(EXIT_IO);
+        }
+    }
+}
+
+fn repl(args: &Args, options: &ProcessOptions) {
+    //Reads transactions typed in one line at a time, starting from the accounts in any files given,
+    //and prints the affected client account after each one. Typing quit, or the end of input, exits
+    let mut clients = initial_clients(args);
+    let mut rejected: Vec<RejectedTransaction> = Vec::new();
+    let mut counts = TransactionCounts::default();
+    for filename in &args.filenames {
+        if let Err(err) = read_csv(filename.to_string(), &mut clients, &mut rejected, &mut counts, options) {
+            eprintln!("Error running readcsv on {}: {}", filename, err);
+            process::exit(exit_code(&err));
+        }
+    }
+    println!("Enter transactions as type, client, tx, amount. Type quit to exit");
+    for (index, line) in io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Error reading input: {}", err);
+                process::exit(EXIT_IO);
+            }
+        };
+        if line.trim() == "quit" {
+            break;
+        }
+        let rejected_before = rejected.len();
+        let client_id = match process_line(&line, index as u64 + 1, &mut clients, &mut rejected, options) {
+            Ok(Some(client_id)) => client_id,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                continue;
+            }
+        };
+        if let Some(transaction) = rejected.get(rejected_before) {
+            println!("Rejected: {:?}", transaction.reason);
+        }
+        match clients.get(&client_id) {
+            Some(client) => {
+                if let Err(err) = write_output(slice::from_ref(client), io::stdout().lock(), &args.output) {
+                    eprintln!("Error writing output: {}", err);
+                    process::exit(EXIT_IO);
+                }
+            }
+            None => println!("No account for client {}", client_id),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+fn read_input(args: &Args, filename: &str, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    //Inputs starting with http:// or https:// are fetched when built with the url feature, anything
+    //else is read as a file, with a progress bar when built with the progress feature and asked for
+    //JSON lines input is always read from a file
+    if args.jsonl {
+        return read_jsonl_file(filename, clients, rejected, counts, options);
+    }
+    #[cfg(feature = "url")]
+    if filename.starts_with("http://") || filename.starts_with("https://") {
+        return read_csv_url(filename, clients, rejected, counts, options);
+    }
+    #[cfg(feature = "progress")]
+    if args.progress {
+        return read_csv_with_progress(filename, clients, rejected, counts, options);
+    }
+    read_csv(filename.to_string(), clients, rejected, counts, options)
+}
+
+#[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+fn read_stdin(args: &Args, clients: &mut HashMap<u16, Client>, rejected: &mut Vec<RejectedTransaction>, counts: &mut TransactionCounts, options: &ProcessOptions) -> Result<(), ProcessError> {
+    //Stdin has no known size, so the progress feature shows a spinner rather than a bar
+    if args.jsonl {
+        return read_jsonl(io::stdin(), clients, rejected, counts, options);
+    }
+    #[cfg(feature = "progress")]
+    if args.progress {
+        return read_csv_reader_with_progress(io::stdin(), clients, rejected, counts, options);
+    }
+    read_csv_reader(io::stdin(), clients, rejected, counts, options)
+}
+
+fn shows_usage(args: &Args, stdin_is_terminal: bool) -> bool {
+    //Without a file name the transactions are read from stdin, which only makes sense when something
+    //is piped in. Run from a terminal with no file the program would just wait, so the usage is shown
+    !args.repl && args.filenames.is_empty() && stdin_is_terminal
+}
 
 fn main() {
-    //Reads name of CSV file passed on the command line 
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    let mut clients : Vec<Client> = Vec::new();
-
-    //Reads and processes all transaction in the CSV. If any transaction are in an improper format
-    //prints an error to stdout and exits
-    if let Err(err) = lib::lib::read_csv(filename.to_string(), &mut clients) {
-        println!("Error running readcsv: {}", err);
-        process::exit(1);
-    }
-    //Loops through the final client account list printing account details to stdout
-    println!("client, available, held, total, locked");
-    for client in clients {
-        println!("{},{:.4},{:.4},{:.4},{}", client.client_id.to_string(), client.available, client.held, client.held + client.available, client.locked );
-    }  
-}
\ No newline at end of file
+    //Logging is off unless turned on with RUST_LOG, e.g. RUST_LOG=debug traces every account change to stderr
+    env_logger::init();
+
+    //Reads the names of the CSV files passed on the command line. Without a file name the CSV is read from stdin
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error reading arguments: {}", err);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    //Reads and processes all transaction in each CSV in turn, so account state carries across files.
+    //If any transaction are in an improper format prints an error to stderr and exits
+    let mut options = ProcessOptions::new().threads(args.threads).delimiter(args.delimiter).chronological(args.chronological).new_client_policy(args.new_client_policy).admin_transactions(args.admin_transactions).allow_redispute(!args.no_redispute).type_names(args.type_names.clone()).disabled_types(args.disabled_types.clone()).decimal_comma(args.decimal_comma).has_headers(!args.no_header).idempotent(args.idempotent).overdraft_limit(args.overdraft_limit);
+    if let Some(symbol) = &args.currency_symbol {
+        options = options.currency_symbol(symbol);
+    }
+    if let Some(retain) = args.retain {
+        options = options.retain_transactions(retain);
+    }
+    if let Some(limit) = args.max_transactions_per_client {
+        options = options.max_transactions_per_client(limit);
+    }
+    if shows_usage(&args, io::stdin().is_terminal()) {
+        eprintln!("usage: rustcodingtest [options] <file.csv>...");
+        process::exit(EXIT_USAGE);
+    }
+    if args.validate {
+        process::exit(validate(&args, &options));
+    }
+    if args.repl {
+        repl(&args, &options);
+        return;
+    }
+    let mut clients = initial_clients(&args);
+    let mut rejected: Vec<RejectedTransaction> = Vec::new();
+    let mut counts = TransactionCounts::default();
+    //Only the reading and processing of the transactions is timed, not writing the output
+    let started = Instant::now();
+    if args.filenames.is_empty() {
+        if let Err(err) = read_stdin(&args, &mut clients, &mut rejected, &mut counts, &options) {
+            eprintln!("Error running readcsv: {}", err);
+            process::exit(exit_code(&err));
+        }
+    }
+    for filename in &args.filenames {
+        if let Err(err) = read_input(&args, filename, &mut clients, &mut rejected, &mut counts, &options) {
+            eprintln!("Error running readcsv on {}: {}", filename, err);
+            process::exit(exit_code(&err));
+        }
+    }
+    let elapsed = started.elapsed();
+    let mut report = ProcessReport { clients: sorted_clients(clients), counts, rejected };
+    //Checks the final accounts against their transactions before anything is written, so a
+    //failed check leaves no output or saved state behind
+    if args.check {
+        if let Err(err) = check_conservation(&report) {
+            eprintln!("Conservation check failed: {}", err);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+    if let Some(path) = &args.save_state {
+        if let Err(err) = save_state(&report.clients, path) {
+            eprintln!("Error saving state to {}: {}", path, err);
+            process::exit(EXIT_IO);
+        }
+    }
+    //Every client is processed, checked and saved, but with --clients only the listed ones are written
+    if let Some(listed) = &args.clients {
+        report.clients.retain(|client| listed.contains(&client.client_id));
+    }
+    //Writes the final client account list, ordered by client id, to the output file or stdout through
+    //a buffer so the output is not flushed for every line. With --disputes the transactions still in
+    //dispute are written instead. With --quiet nothing is written to stdout, though an output file still is
+    let writer: io::Result<Box<dyn io::Write>> = match &args.output_path {
+        Some(path) => File::create(path).map(|file| Box::new(file) as Box<dyn io::Write>),
+        None if args.quiet => Ok(Box::new(io::sink())),
+        None => Ok(Box::new(io::stdout().lock())),
+    };
+    let result = writer.and_then(|writer| {
+        if args.disputes {
+            write_disputes(&report.clients, BufWriter::new(writer), &args.output)
+        } else {
+            write_output(&report.clients, BufWriter::new(writer), &args.output)
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("Error writing output: {}", err);
+        process::exit(EXIT_IO);
+    }
+    //The stats go to stderr so they are not mixed in with the account details
+    if args.stats {
+        let counts = &report.counts;
+        eprintln!("rows {}, deposits {}, withdrawals {}, disputes {}, resolves {}, chargebacks {}, skipped {}, rejected {}",
+            counts.rows, counts.deposits, counts.withdrawals, counts.disputes, counts.resolves, counts.chargebacks, counts.skipped, report.rejected.len());
+        eprintln!("processed {} rows in {:.3}s, {:.0} rows per second", counts.rows, elapsed.as_secs_f64(), counts.rows as f64 / elapsed.as_secs_f64());
+    }
+    //Every transaction was read, but some were not applied to any account
+    if !report.rejected.is_empty() {
+        process::exit(EXIT_REJECTED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    #[test]
+    fn usage_is_shown_without_a_file_only_from_a_terminal() {
+        assert!(shows_usage(&parse(&[]), true));
+        assert!(!shows_usage(&parse(&[]), false));
+        assert!(!shows_usage(&parse(&["transactions.csv"]), true));
+        assert!(!shows_usage(&parse(&["--repl"]), true));
+    }
+}
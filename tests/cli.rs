@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use rustcodingtest::{process_events, ProcessOptions};
+
+fn run(args: &[&str]) -> Output {
+    //Runs the binary from the crate root, where the test files are, with nothing on stdin
+    Command::new(env!("CARGO_BIN_EXE_rustcodingtest"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn exit_code(args: &[&str]) -> Option<i32> {
+    run(args).status.code()
+}
+
+#[test]
+fn processed_file_exits_with_success() {
+    let output = run(&["transactions_reused_ids.csv"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("client, available, held, total, locked, status"));
+}
+
+#[test]
+fn failed_check_exits_with_1() {
+    assert_eq!(exit_code(&["--check", "--load-state", "transactions_state_corrupted.json", "transactions_empty.csv"]), Some(1));
+}
+
+#[test]
+fn invalid_arguments_exit_with_2() {
+    let output = run(&["--threads", "x", "transactions.csv"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error reading arguments"));
+}
+
+#[test]
+fn missing_file_exits_with_3() {
+    let output = run(&["transactions_missing.csv"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("transactions_missing.csv"));
+}
+
+#[test]
+fn malformed_file_exits_with_4() {
+    assert_eq!(exit_code(&["transactions_malformed_row.csv"]), Some(4));
+    assert_eq!(exit_code(&["--validate", "transactions_single_account_errors.csv"]), Some(4));
+}
+
+#[test]
+fn rejected_transactions_exit_with_5() {
+    assert_eq!(exit_code(&["transactions.csv"]), Some(5));
+}
+
+#[test]
+fn no_file_reads_stdin_when_it_is_not_a_terminal() {
+    //Nothing piped in is read as an empty file rather than showing the usage
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "client, available, held, total, locked, status\n");
+}
+
+#[test]
+fn selected_columns_are_written_with_their_requested_names() {
+    let output = run(&["--columns", "client_id,total,locked", "transactions_reused_ids.csv"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("client_id, total, locked"));
+    assert!(lines.all(|line| line.split(',').count() == 3));
+    assert_eq!(exit_code(&["--columns", "client,balance", "transactions_reused_ids.csv"]), Some(2));
+}
+
+#[test]
+fn debug_log_has_a_line_for_each_applied_transaction() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustcodingtest"))
+        .arg("transactions_reused_ids.csv")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("RUST_LOG", "debug")
+        .output()
+        .unwrap();
+    let logged = String::from_utf8_lossy(&output.stderr).lines().filter(|line| line.contains(" DEBUG ")).count();
+    let file = File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("transactions_reused_ids.csv")).unwrap();
+    let applied = process_events(file, &ProcessOptions::new()).unwrap().count();
+    assert_eq!(logged, applied);
+    assert_eq!(applied, 4);
+}
+
+#[test]
+fn delimiter_of_more_than_one_character_exits_with_2() {
+    let output = run(&["--delimiter", ";;", "transactions.csv"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("delimiter must be a single ASCII character"));
+}
+
+#[test]
+fn clients_option_writes_only_the_listed_clients() {
+    let output = run(&["--clients", "7,300", "transactions_unordered_clients.csv"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let clients: Vec<_> = stdout.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+    assert_eq!(clients, vec!["7", "300"]);
+    let output = run(&["--clients", "7,x", "transactions_unordered_clients.csv"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("got 'x'"));
+}
+
+#[test]
+fn dir_option_processes_every_csv_file_in_the_directory() {
+    //The chargeback on the second day acts on the deposit from the first, and README.txt is not read
+    let output = run(&["--dir", "transactions_daily"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "client, available, held, total, locked, status\n1,8.5000,0.0000,8.5000,false,active\n2,0.0000,0.0000,0.0000,true,charged_back\n");
+    let output = run(&["--dir", "transactions_missing"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("can not read directory transactions_missing"));
+}
+
+#[test]
+fn type_name_option_maps_names_to_transaction_types() {
+    let output = run(&["--type-name", "credit=deposit", "--type-name", "debit=withdrawal", "--type-name", "reversal=dispute", "transactions_renamed_types.csv"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "client, available, held, total, locked, status\n1,-3.0000,0.0000,-3.0000,true,charged_back\n");
+    assert_eq!(exit_code(&["--type-name", "credit", "transactions_renamed_types.csv"]), Some(2));
+}
+
+#[test]
+fn decimal_comma_with_a_comma_delimiter_exits_with_2() {
+    let output = run(&["--decimal-comma", "transactions_decimal_comma.csv"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--decimal-comma needs a --delimiter other than a comma"));
+    assert_eq!(exit_code(&["--delimiter", ";", "--decimal-comma", "transactions_decimal_comma.csv"]), Some(0));
+}
+
+#[test]
+fn quiet_writes_nothing_to_stdout_but_keeps_the_exit_code() {
+    let output = run(&["--quiet", "transactions.csv"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(output.stdout.is_empty());
+    let output = run(&["--quiet", "--validate", "transactions_single_account_errors.csv"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+}